@@ -1,5 +1,6 @@
 use crate::egg::Egg;
 use crate::formater::InventoryFormat;
+use crate::protocol::{HasId, Id};
 use crate::resources::{Resource, Resources};
 use std::fmt;
 
@@ -10,16 +11,24 @@ pub struct Cell {
 }
 
 impl Cell {
-    pub fn new() -> Self {
+    pub const fn new() -> Self {
         Cell {
-            resources: Resources::default(),
+            resources: Resources::new(),
             eggs: Vec::new(),
         }
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.resources.is_empty() && self.eggs.is_empty()
+    }
+
     pub fn ressources(&self) -> &Resources {
         &self.resources
     }
+
+    pub fn eggs(&self) -> &[Egg] {
+        &self.eggs
+    }
 }
 
 const GREEN: &str = "\x1b[32m";
@@ -27,6 +36,9 @@ const RESET: &str = "\x1b[0m";
 
 impl fmt::Display for Cell {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.resources.is_empty() {
+            return write!(f, "([],{GREEN}{}{RESET})", self.eggs.len());
+        }
         write!(
             f,
             "({},{GREEN}{}{RESET})",
@@ -49,4 +61,18 @@ impl Cell {
             None
         }
     }
+
+    pub fn add_egg(&mut self, egg: Egg) {
+        self.eggs.push(egg);
+    }
+
+    pub fn remove_egg(&mut self, egg_id: Id) -> Option<Egg> {
+        let index = self.eggs.iter().position(|egg| egg.id() == egg_id)?;
+        Some(self.eggs.remove(index))
+    }
+
+    /// Removes every egg of the tile, in laying order
+    pub fn take_eggs(&mut self) -> Vec<Egg> {
+        std::mem::take(&mut self.eggs)
+    }
 }
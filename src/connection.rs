@@ -1,19 +1,22 @@
-use crate::constant::MAX_LINE_SIZE;
+use crate::constant::{DEFAULT_MAX_QUEUED_COMMANDS, MAX_LINE_SIZE, WRITE_STALL_TIMEOUT};
 use crate::handler::ai::AiHandler;
 use crate::handler::command::{CommandHandler, CommandRes, State};
 use crate::handler::graphics::GraphicHandler;
 use crate::handler::login::LoginHandler;
 use crate::protocol::{EventType, ServerResponse, SharedAction};
-use log::{debug, error, warn};
-use std::time::Duration;
+use log::{debug, error, info, warn};
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use thiserror::Error;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::Receiver;
 use tokio::task::JoinHandle;
-use tokio::time::timeout;
+use tokio::time::{Duration, timeout};
 
 /// Manages a TCP connection with a client
 pub struct Connection {
@@ -21,6 +24,49 @@ pub struct Connection {
     // Channel to send events to server
     server_tx: mpsc::Sender<EventType>,
     command_handler: Box<dyn CommandHandler + Send>,
+    trace: bool,
+    stats: TrafficStats,
+    login: LoginState,
+    // Input read while the login is unanswered, parsed once the client's role is known
+    held_inputs: VecDeque<ClientInput>,
+    // Input held past this is dropped, a client can't queue more commands than that anyway
+    max_held_inputs: usize,
+}
+
+/// What a client sent: a line, or one that couldn't be read. Both get one reply
+#[derive(Debug)]
+enum ClientInput {
+    Line(String),
+    Error(SharedAction),
+}
+
+/// Input held during the login, the queue limit plus one more login attempt
+/// in case the first is refused
+fn max_held_inputs(max_queued_commands: u64) -> usize {
+    max_queued_commands as usize + 1
+}
+
+#[derive(Debug, PartialEq)]
+enum LoginState {
+    Pending,
+    AwaitingReply,
+    Done,
+}
+
+/// What went over the wire with one client, logged when it disconnects
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct TrafficStats {
+    pub bytes_received: u64,
+    pub lines_received: u64,
+    pub bytes_sent: u64,
+    pub lines_sent: u64,
+}
+
+impl TrafficStats {
+    fn record_sent(&mut self, data: &str) {
+        self.bytes_sent += data.len() as u64;
+        self.lines_sent += data.matches('\n').count() as u64;
+    }
 }
 
 #[derive(Debug, Error)]
@@ -45,12 +91,128 @@ enum RecvError {
     InvalidUTF8,
     #[error("Client message exceeded maximum length")]
     ReachedTakeLimit,
+    #[error("IO error: {0}")]
+    Io(ErrorKind),
+}
+
+impl RecvError {
+    /// Whether the connection can't be read from anymore
+    fn is_fatal(&self) -> bool {
+        matches!(self, RecvError::Closed | RecvError::Io(_))
+    }
+}
+
+/// Reads a line into `line`, never buffering more than `MAX_LINE_SIZE` bytes.
+///
+/// When a line is too long, the rest of it is discarded up to the next newline
+/// so the following line can still be read.
+async fn read_bounded_line<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    line: &mut Vec<u8>,
+) -> Result<(), RecvError> {
+    let mut overflowed = false;
+
+    loop {
+        let available = reader
+            .fill_buf()
+            .await
+            .map_err(|e| RecvError::Io(e.kind()))?;
+        if available.is_empty() {
+            if overflowed {
+                return Err(RecvError::ReachedTakeLimit);
+            }
+            return if line.is_empty() {
+                Err(RecvError::Closed)
+            } else {
+                Ok(())
+            };
+        }
+
+        let (chunk_len, found_newline) = match available.iter().position(|&b| b == b'\n') {
+            Some(index) => (index + 1, true),
+            None => (available.len(), false),
+        };
+        if !overflowed {
+            if line.len() + chunk_len > MAX_LINE_SIZE {
+                overflowed = true;
+                line.clear();
+            } else {
+                line.extend_from_slice(&available[..chunk_len]);
+            }
+        }
+        reader.consume(chunk_len);
+
+        if found_newline {
+            return if overflowed {
+                Err(RecvError::ReachedTakeLimit)
+            } else {
+                Ok(())
+            };
+        }
+    }
+}
+
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<String, RecvError> {
+    let mut line = Vec::new();
+    read_bounded_line(reader, &mut line).await?;
+    String::from_utf8(line).map_err(|_| RecvError::InvalidUTF8)
+}
+
+/// Removes the line terminator, keeping any other trailing whitespace as part of the arguments
+fn strip_line_ending(line: &str) -> &str {
+    line.trim_end_matches(['\r', '\n'])
+}
+
+/// Writes `data`, only timing out when the peer stops accepting bytes for `stall_timeout`.
+///
+/// A slow client that keeps reading is never cut off, and a write error
+/// (e.g. broken pipe) is reported as `IoError` rather than `Timeout`.
+async fn write_with_stall_timeout<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    data: &[u8],
+    stall_timeout: Duration,
+) -> Result<(), ConnectionError> {
+    let mut written = 0;
+
+    while written < data.len() {
+        let n = timeout(stall_timeout, writer.write(&data[written..]))
+            .await
+            .map_err(|_| ConnectionError::Timeout)??;
+        if n == 0 {
+            return Err(ConnectionError::IoError(ErrorKind::WriteZero.into()));
+        }
+        written += n;
+    }
+    Ok(())
+}
+
+/// Counts an open connection until dropped
+pub struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl ConnectionSlot {
+    /// Takes a slot unless `max` connections are already open
+    pub fn try_acquire(count: &Arc<AtomicUsize>, max: usize) -> Option<Self> {
+        count
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| ConnectionSlot(count.clone()))
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
 }
 
 enum ConnectionEvent {
     ClientMessage(String),
     ClientError(RecvError),
     ServerResponse(ServerResponse),
+    // The server dropped its end, nothing will be sent to this client anymore
+    ServerClosed,
 }
 
 impl Connection {
@@ -60,27 +222,52 @@ impl Connection {
     /// * `id` - Unique identifier for this connection
     /// * `socket` - TCP socket connected to the client
     /// * `server_tx` - Channel to send events to the server
+    /// * `welcome` - Greeting written before anything is read
     pub async fn new(
         id: u64,
         socket: TcpStream,
         server_tx: mpsc::Sender<EventType>,
+        welcome: &[u8],
     ) -> (Self, BufReader<OwnedReadHalf>) {
         let (read_half, write_half) = socket.into_split();
         let mut writer = write_half;
 
         // Send welcome message, ignoring errors (will be handled in update loop)
-        let _ = writer.write_all(b"WELCOME\n").await;
+        let mut stats = TrafficStats::default();
+        if writer.write_all(welcome).await.is_ok() {
+            stats.record_sent(&String::from_utf8_lossy(welcome));
+        }
 
         (
             Self {
                 writer,
                 server_tx,
                 command_handler: Box::new(LoginHandler::new(id)),
+                trace: false,
+                stats,
+                login: LoginState::Pending,
+                held_inputs: VecDeque::new(),
+                max_held_inputs: max_held_inputs(DEFAULT_MAX_QUEUED_COMMANDS),
             },
             BufReader::new(read_half),
         )
     }
 
+    /// Logs every line exchanged with the client at info level
+    pub fn set_trace(&mut self, enabled: bool) {
+        self.trace = enabled;
+    }
+
+    /// Bounds the input held while the login is unanswered to the server's queue limit
+    pub fn set_max_queued_commands(&mut self, max_queued_commands: u64) {
+        self.max_held_inputs = max_held_inputs(max_queued_commands);
+    }
+
+    /// Traffic so far, lets "never sent" be told apart from "never read" for a stale client
+    pub fn stats(&self) -> TrafficStats {
+        self.stats
+    }
+
     /// Main connection handling loop
     pub async fn handle(
         &mut self,
@@ -98,39 +285,41 @@ impl Connection {
         'main: while let Some(event) = event_rx.recv().await {
             match event {
                 ConnectionEvent::ClientMessage(line) => {
-                    let line = line.trim_end();
+                    self.stats.bytes_received += line.len() as u64;
+                    self.stats.lines_received += 1;
+                    let line = strip_line_ending(&line);
+                    if self.trace {
+                        info!("Client {} <- {}", self.command_handler.id(), line);
+                    }
 
-                    let action = self.command_handler.parse_command(line.to_string());
-                    let _ = self.server_tx.send(action).await;
+                    self.forward_input(ClientInput::Line(line.to_string()))
+                        .await;
                 }
                 ConnectionEvent::ClientError(err) => {
                     match &err {
                         RecvError::Closed => {
                             warn!("Client {}: Connection closed", self.command_handler.id());
                         }
+                        RecvError::Io(kind) => {
+                            warn!(
+                                "Client {}: Connection lost: {}",
+                                self.command_handler.id(),
+                                kind
+                            );
+                        }
                         RecvError::InvalidUTF8 => {
                             warn!("Client {}: Invalid UTF-8 data", self.command_handler.id());
-                            let _ = self
-                                .server_tx
-                                .send(
-                                    self.command_handler
-                                        .create_shared_event(SharedAction::InvalidEncoding),
-                                )
+                            self.forward_input(ClientInput::Error(SharedAction::InvalidEncoding))
                                 .await;
                         }
                         RecvError::ReachedTakeLimit => {
                             warn!("Client {}: Message too long", self.command_handler.id());
-                            let _ = self
-                                .server_tx
-                                .send(
-                                    self.command_handler
-                                        .create_shared_event(SharedAction::ReachedTakeLimit),
-                                )
+                            self.forward_input(ClientInput::Error(SharedAction::ReachedTakeLimit))
                                 .await;
                         }
                     };
 
-                    if matches!(err, RecvError::Closed) {
+                    if err.is_fatal() {
                         result = Err(ConnectionError::Disconnected);
                         break 'main;
                     }
@@ -149,10 +338,14 @@ impl Connection {
                                 result = Err(e);
                                 break 'main;
                             }
+                            self.login = LoginState::Done;
+                            self.release_held_inputs().await;
                         }
                         CommandRes::ChangeState(State::GUI) => {
                             self.command_handler =
                                 Box::new(GraphicHandler::new(self.command_handler.id()));
+                            self.login = LoginState::Done;
+                            self.release_held_inputs().await;
                         }
                         CommandRes::Response(res) => {
                             if let Err(e) = self.send_response_with_timeout(res).await {
@@ -164,19 +357,61 @@ impl Connection {
                                 result = Err(e);
                                 break 'main;
                             }
+                            // A refused login or an unreadable line, the next held line may be
+                            // another attempt
+                            if self.login == LoginState::AwaitingReply {
+                                self.login = LoginState::Pending;
+                                self.release_held_inputs().await;
+                            }
                         }
                         CommandRes::ChangeState(State::DEAD(res)) => {
-                            let _ = self.send_response_with_timeout(res).await;
+                            // The writer is shut down after the loop, once `dead` is flushed
+                            if let Err(e) = self.send_response_with_timeout(res).await {
+                                warn!(
+                                    "Client {}: Failed to send dead message: {}",
+                                    self.command_handler.id(),
+                                    e
+                                );
+                            } else if let Err(e) = self.writer.flush().await {
+                                warn!(
+                                    "Client {}: Failed to flush dead message: {}",
+                                    self.command_handler.id(),
+                                    e
+                                );
+                            }
                             result = Err(ConnectionError::ForciblyClosedByServer);
                             break 'main;
                         }
                     }
                 }
+                ConnectionEvent::ServerClosed => {
+                    info!(
+                        "Client {}: Server closed the connection",
+                        self.command_handler.id()
+                    );
+                    break 'main;
+                }
             }
         }
 
+        // Lets the peer see EOF right away rather than whenever the connection is dropped
+        if let Err(e) = self.writer.shutdown().await {
+            debug!(
+                "Client {}: Failed to shut down writer: {}",
+                self.command_handler.id(),
+                e
+            );
+        }
         reader_task.abort();
         server_task.abort();
+        info!(
+            "Client {}: received {} lines ({} bytes), sent {} lines ({} bytes)",
+            self.command_handler.id(),
+            self.stats.lines_received,
+            self.stats.bytes_received,
+            self.stats.lines_sent,
+            self.stats.bytes_sent
+        );
 
         if result.is_err() {
             self.server_tx
@@ -189,6 +424,43 @@ impl Connection {
         result
     }
 
+    /// Parses a client input with the current handler and hands it to the server.
+    ///
+    /// Until the login is answered the handler that must parse the next lines is
+    /// unknown, so they are held back instead of being read as team names. Unreadable
+    /// lines wait too, their `ko` would otherwise be taken for the login's answer
+    async fn forward_input(&mut self, input: ClientInput) {
+        match self.login {
+            LoginState::AwaitingReply => {
+                if self.held_inputs.len() < self.max_held_inputs {
+                    self.held_inputs.push_back(input);
+                } else {
+                    warn!(
+                        "Client {}: Too many lines before the login is answered, dropped",
+                        self.command_handler.id()
+                    );
+                }
+                return;
+            }
+            LoginState::Pending => self.login = LoginState::AwaitingReply,
+            LoginState::Done => {}
+        }
+        let event = match input {
+            ClientInput::Line(line) => self.command_handler.parse_command(line),
+            ClientInput::Error(action) => self.command_handler.create_shared_event(action),
+        };
+        let _ = self.server_tx.send(event).await;
+    }
+
+    async fn release_held_inputs(&mut self) {
+        while self.login != LoginState::AwaitingReply {
+            let Some(input) = self.held_inputs.pop_front() else {
+                break;
+            };
+            self.forward_input(input).await;
+        }
+    }
+
     /// Spawn a task that reads from the client socket
     fn spawn_reader_task(
         &self,
@@ -197,16 +469,6 @@ impl Connection {
     ) -> JoinHandle<()> {
         let client_id = self.command_handler.id();
 
-        async fn read_line(reader_half: &mut  BufReader<OwnedReadHalf>) -> Result<String, RecvError> {
-            let mut line = String::new();
-            match reader_half.read_line(&mut line).await {
-                Ok(0) => Err(RecvError::Closed),
-                Ok(n) if n > MAX_LINE_SIZE => Err(RecvError::ReachedTakeLimit),
-                Ok(_) => Ok(line),
-                Err(_) => Err(RecvError::InvalidUTF8),
-            }
-        }
-
         tokio::spawn(async move {
             loop {
                 match read_line(&mut reader_half).await {
@@ -222,7 +484,7 @@ impl Connection {
                     }
                     Err(e) => {
                         let _ = event_tx.send(ConnectionEvent::ClientError(e.clone())).await;
-                        if matches!(e, RecvError::Closed) {
+                        if e.is_fatal() {
                             debug!(
                                 "Client {}: Connection closed, reader task exiting",
                                 client_id
@@ -255,17 +517,329 @@ impl Connection {
                 }
             }
             debug!("Client {}: Server channel closed, task exiting", client_id);
+            let _ = event_tx.send(ConnectionEvent::ServerClosed).await;
         })
     }
 
     async fn send_response_with_timeout(&mut self, res: String) -> Result<(), ConnectionError> {
-        let writer = &mut self.writer;
+        if self.trace {
+            for line in res.lines() {
+                info!("Client {} -> {}", self.command_handler.id(), line);
+            }
+        }
+        write_with_stall_timeout(&mut self.writer, res.as_bytes(), WRITE_STALL_TIMEOUT).await?;
+        self.stats.record_sent(&res);
+        Ok(())
+    }
+}
 
-        timeout(Duration::from_secs(5), async {
-            writer.write_all(res.as_bytes()).await?;
-            Ok(())
-        })
-        .await
-        .unwrap_or(Err(ConnectionError::Timeout))
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{AIAction, AIEvent, AIResponse, PendingResponse, TeamType};
+    use crate::vec2::UPosition;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+
+    struct ResetReader;
+
+    impl AsyncRead for ResetReader {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            _: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Err(ErrorKind::ConnectionReset.into()))
+        }
+    }
+
+    #[test]
+    fn test_connection_slot() {
+        let count = Arc::new(AtomicUsize::new(0));
+
+        let first = ConnectionSlot::try_acquire(&count, 2).unwrap();
+        let _second = ConnectionSlot::try_acquire(&count, 2).unwrap();
+        assert!(ConnectionSlot::try_acquire(&count, 2).is_none());
+        assert_eq!(count.load(Ordering::Acquire), 2);
+
+        drop(first);
+        assert_eq!(count.load(Ordering::Acquire), 1);
+        assert!(ConnectionSlot::try_acquire(&count, 2).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_read_line_invalid_utf8() {
+        let mut reader = BufReader::new(&b"Take \xff\xfe\n"[..]);
+
+        let err = read_line(&mut reader).await.unwrap_err();
+
+        assert!(matches!(err, RecvError::InvalidUTF8));
+        assert!(!err.is_fatal());
+    }
+
+    #[tokio::test]
+    async fn test_read_line_connection_reset() {
+        let mut reader = BufReader::new(ResetReader);
+
+        let err = read_line(&mut reader).await.unwrap_err();
+
+        assert!(matches!(err, RecvError::Io(ErrorKind::ConnectionReset)));
+        assert!(err.is_fatal());
+    }
+
+    #[tokio::test]
+    async fn test_read_line_too_long_is_bounded() {
+        let data = vec![b'a'; 1024 * 1024];
+        let mut reader = BufReader::new(&data[..]);
+        let mut line = Vec::new();
+
+        let err = read_bounded_line(&mut reader, &mut line).await.unwrap_err();
+
+        assert!(matches!(err, RecvError::ReachedTakeLimit));
+        assert!(line.len() <= MAX_LINE_SIZE);
+        assert!(line.capacity() <= 2 * MAX_LINE_SIZE);
+        assert!(matches!(
+            read_line(&mut reader).await.unwrap_err(),
+            RecvError::Closed
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_read_line_resumes_after_too_long_line() {
+        let mut data = vec![b'a'; MAX_LINE_SIZE * 4];
+        data.extend_from_slice(b"\nLook\n");
+        let mut reader = BufReader::new(&data[..]);
+
+        assert!(matches!(
+            read_line(&mut reader).await.unwrap_err(),
+            RecvError::ReachedTakeLimit
+        ));
+        assert_eq!(read_line(&mut reader).await.unwrap(), "Look\n");
+    }
+
+    #[tokio::test]
+    async fn test_read_line_max_size() {
+        let mut data = vec![b'a'; MAX_LINE_SIZE - 1];
+        data.push(b'\n');
+        let mut reader = BufReader::new(&data[..]);
+
+        assert_eq!(read_line(&mut reader).await.unwrap().len(), MAX_LINE_SIZE);
+    }
+
+    #[tokio::test]
+    async fn test_write_broken_pipe_is_io_error() {
+        let (mut writer, reader) = tokio::io::duplex(64);
+        drop(reader);
+
+        let err = write_with_stall_timeout(&mut writer, b"ok\n", Duration::from_millis(50))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, ConnectionError::IoError(e) if e.kind() == ErrorKind::BrokenPipe));
+    }
+
+    #[tokio::test]
+    async fn test_write_stalled_client_times_out() {
+        let (mut writer, _reader) = tokio::io::duplex(16);
+
+        let err = write_with_stall_timeout(&mut writer, &[b'a'; 64], Duration::from_millis(50))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, ConnectionError::Timeout));
+    }
+
+    #[tokio::test]
+    async fn test_write_slow_client_making_progress() {
+        let (mut writer, mut reader) = tokio::io::duplex(16);
+        let slow_reader = tokio::spawn(async move {
+            let mut received = Vec::new();
+            let mut chunk = [0u8; 16];
+            while received.len() < 256 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                let n = reader.read(&mut chunk).await.unwrap();
+                received.extend_from_slice(&chunk[..n]);
+            }
+            received
+        });
+
+        write_with_stall_timeout(&mut writer, &[b'a'; 256], Duration::from_millis(50))
+            .await
+            .unwrap();
+
+        assert_eq!(slow_reader.await.unwrap(), vec![b'a'; 256]);
+    }
+
+    #[tokio::test]
+    async fn test_read_line_eof() {
+        let mut reader = BufReader::new(&b"Forward\n"[..]);
+
+        assert_eq!(read_line(&mut reader).await.unwrap(), "Forward\n");
+        assert!(matches!(
+            read_line(&mut reader).await.unwrap_err(),
+            RecvError::Closed
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_writer_is_shut_down_when_server_closes() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut peer = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let (server_tx, mut server_rx) = mpsc::channel(8);
+        let (client_tx, client_rx) = mpsc::channel(8);
+
+        let (mut connection, read_half) = Connection::new(0, socket, server_tx, b"WELCOME\n").await;
+        client_tx
+            .send(ServerResponse::Pending(PendingResponse::LogAs(
+                TeamType::IA(3, UPosition::new(10, 10)),
+            )))
+            .await
+            .unwrap();
+        client_tx
+            .send(ServerResponse::AI(AIResponse::Dead))
+            .await
+            .unwrap();
+
+        let result = connection.handle(client_rx, read_half).await;
+
+        assert!(matches!(
+            result,
+            Err(ConnectionError::ForciblyClosedByServer)
+        ));
+        // The connection is still alive, only the shutdown can end the stream
+        let mut received = String::new();
+        peer.read_to_string(&mut received).await.unwrap();
+        assert_eq!(received, "WELCOME\n3\n10 10\ndead\n");
+        assert!(matches!(
+            server_rx.recv().await,
+            Some(EventType::AI(AIEvent {
+                action: AIAction::Shared(SharedAction::Disconnected),
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_strip_line_ending_keeps_trailing_spaces() {
+        assert_eq!(strip_line_ending("Broadcast hello \n"), "Broadcast hello ");
+        assert_eq!(strip_line_ending("Forward"), "Forward");
+
+        let mut handler = AiHandler::new(1);
+        let event = handler.parse_command(strip_line_ending("Broadcast hello \n").to_string());
+        assert!(matches!(
+            event,
+            EventType::AI(AIEvent {
+                action: AIAction::Action(crate::event::Event::Broadcast(message)),
+                ..
+            }) if message == "hello "
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_crlf_and_lf_lines_parse_identically() {
+        let mut reader = BufReader::new(&b"Take food\r\nTake food\n"[..]);
+        let crlf = read_line(&mut reader).await.unwrap();
+        let lf = read_line(&mut reader).await.unwrap();
+        assert_eq!(strip_line_ending(&crlf), strip_line_ending(&lf));
+
+        let mut handlers: [Box<dyn CommandHandler>; 3] = [
+            Box::new(LoginHandler::new(1)),
+            Box::new(AiHandler::new(1)),
+            Box::new(GraphicHandler::new(1)),
+        ];
+        let commands: [&[&str]; 3] = [
+            &["team1", "GRAPHIC"],
+            &["Forward", "Broadcast hello world", "Set thystame"],
+            &["msz", "bct 1 2", "ppo #3", "sst 10", "sub positions"],
+        ];
+
+        for (handler, commands) in handlers.iter_mut().zip(commands) {
+            for command in commands {
+                let crlf =
+                    handler.parse_command(strip_line_ending(&format!("{command}\r\n")).into());
+                let lf = handler.parse_command(strip_line_ending(&format!("{command}\n")).into());
+                assert_eq!(format!("{:?}", crlf), format!("{:?}", lf), "{:?}", command);
+                assert!(!format!("{:?}", crlf).contains("Invalid"), "{:?}", command);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_traffic_is_counted() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut peer = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let (server_tx, mut server_rx) = mpsc::channel(8);
+        let (client_tx, client_rx) = mpsc::channel(8);
+
+        let (mut connection, read_half) = Connection::new(0, socket, server_tx, b"WELCOME\n").await;
+        peer.write_all(b"team1\r\n").await.unwrap();
+        let responses = async {
+            assert!(matches!(
+                server_rx.recv().await,
+                Some(EventType::Pending(_))
+            ));
+            client_tx
+                .send(ServerResponse::Pending(PendingResponse::LogAs(
+                    TeamType::IA(3, UPosition::new(10, 10)),
+                )))
+                .await
+                .unwrap();
+            client_tx
+                .send(ServerResponse::AI(AIResponse::Dead))
+                .await
+                .unwrap();
+        };
+
+        let (result, _) = tokio::join!(connection.handle(client_rx, read_half), responses);
+
+        assert!(result.is_err());
+        assert_eq!(
+            connection.stats(),
+            TrafficStats {
+                bytes_received: 7,
+                lines_received: 1,
+                bytes_sent: "WELCOME\n3\n10 10\ndead\n".len() as u64,
+                lines_sent: 4,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lines_held_during_login_are_bounded() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let _peer = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+        let (server_tx, mut server_rx) = mpsc::channel(64);
+
+        let (mut connection, _read_half) = Connection::new(0, socket, server_tx, b"").await;
+        connection.set_max_queued_commands(2);
+        connection
+            .forward_input(ClientInput::Line("team1".to_string()))
+            .await;
+        for _ in 0..20 {
+            connection
+                .forward_input(ClientInput::Line("Forward".to_string()))
+                .await;
+        }
+        assert_eq!(connection.held_inputs.len(), 3);
+
+        connection.command_handler = Box::new(AiHandler::new(0));
+        connection.login = LoginState::Done;
+        connection.release_held_inputs().await;
+        drop(connection);
+        let mut forwarded = 0;
+        while server_rx.recv().await.is_some() {
+            forwarded += 1;
+        }
+        assert_eq!(forwarded, 4);
     }
 }
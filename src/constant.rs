@@ -1,8 +1,31 @@
 use crate::player::RelativeDirection;
+use crate::protocol::Id;
+use std::time::Duration;
 
 pub const REFILL_PER_FOOD: u64 = 126;
 pub const SATIETY_LOSS_PER_TICK: u64 = 1;
+/// Largest map allowed by default. Dimensions are `u8`, so no map goes past
+/// 255 * 255 cells (about 5 MB at ~80 bytes a cell) and the default takes them all
+pub const DEFAULT_MAX_MAP_CELLS: u64 = u8::MAX as u64 * u8::MAX as u64;
+/// Highest tick frequency accepted by default, from the config or `sst`
+pub const DEFAULT_MAX_FREQ: u64 = 10_000;
+/// Level 8 players a team needs to win
+pub const WINNING_PLAYERS: u64 = 6;
+/// Ticks a `Fork` takes by default, as in the spec
+pub const DEFAULT_FORK_COST: u64 = 42;
+/// Owner of the scheduled events that belong to no client, e.g. egg expiry
+pub const SERVER_EVENT_OWNER: Id = Id::MAX;
+/// Commands an AI may have queued at once, as in the spec
+pub const DEFAULT_MAX_QUEUED_COMMANDS: u64 = 10;
+/// Ticks without any command after which a logged-in AI is disconnected
+pub const DEFAULT_IDLE_TIMEOUT: u64 = 10_000;
+/// Client events handled per server loop iteration before ticks and accepts get a turn
+pub const DEFAULT_EVENT_BATCH_SIZE: usize = 32;
 pub const MAX_LINE_SIZE: usize = 8193;
+/// How long a client may go without reading any of our output before being dropped
+pub const WRITE_STALL_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a stopping server waits for the connections to flush their last messages
+pub const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(5);
 pub const RELATIVE_DIRECTIONS: [RelativeDirection; 4] = [
     RelativeDirection::Back,
     RelativeDirection::Left,
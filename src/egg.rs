@@ -6,16 +6,27 @@ pub struct Egg {
     id: Id,
     team_id: Id,
     pos: UPosition,
+    // Player that forked it, `None` for the eggs laid when the game starts
+    layer: Option<Id>,
 }
 
 impl Egg {
-    pub fn new(id: Id, team_id: Id, pos: UPosition) -> Self {
-        Egg { id, team_id, pos }
+    pub fn new(id: Id, team_id: Id, pos: UPosition, layer: Option<Id>) -> Self {
+        Egg {
+            id,
+            team_id,
+            pos,
+            layer,
+        }
     }
 
     pub fn team_id(&self) -> Id {
         self.team_id
     }
+
+    pub fn layer(&self) -> Option<Id> {
+        self.layer
+    }
 }
 
 impl HasId for Egg {
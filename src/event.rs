@@ -1,13 +1,12 @@
+use crate::constant::DEFAULT_MAX_QUEUED_COMMANDS;
 use crate::protocol::Id;
 use crate::resources::{LevelRequirement, Resource};
 use crate::vec2::UPosition;
 use log::{debug, trace, warn};
 use std::cmp::Ordering;
-use std::collections::BinaryHeap;
+use std::collections::{BinaryHeap, HashMap};
 use std::fmt::Debug;
 
-const MAX_SIMULTANEOUS_EVENTS: u64 = 10;
-
 #[derive(Debug)]
 pub enum Event {
     Broadcast(String),
@@ -22,11 +21,13 @@ pub enum Event {
     Take(Resource),
     Set(Resource),
     Incantation,
+    Time, // Non-spec, only answered when enabled in the config
 
     //Can't be sent by IA
     Ko,
     Phantom, // Phantom Event, does almost nothing, only exists to make a client wait for this event
     IncantationEnd(Vec<Id>, &'static LevelRequirement, UPosition),
+    EggExpire(Id),
 }
 
 #[derive(Debug, Clone)]
@@ -35,9 +36,20 @@ pub struct TimedEvent<T> {
     pub event_id: Id,
     pub player_id: Id,
     pub expiration_tick: u64,
+    // Sent by the client, only these count toward its queue limit
+    pub client_initiated: bool,
+}
+
+/// Position of an event in the queue. Reversed so the max-heap pops the
+/// earliest tick first, events expiring on the same tick run in scheduling
+/// (`event_id`) order
+#[derive(PartialEq, Eq)]
+struct QueueKey {
+    expiration_tick: u64,
+    event_id: Id,
 }
 
-impl<T> Ord for TimedEvent<T> {
+impl Ord for QueueKey {
     fn cmp(&self, other: &Self) -> Ordering {
         other
             .expiration_tick
@@ -46,20 +58,12 @@ impl<T> Ord for TimedEvent<T> {
     }
 }
 
-impl<T> PartialOrd for TimedEvent<T> {
+impl PartialOrd for QueueKey {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
 
-impl<T> PartialEq for TimedEvent<T> {
-    fn eq(&self, other: &Self) -> bool {
-        self.expiration_tick == other.expiration_tick && self.event_id == other.event_id
-    }
-}
-
-impl<T> Eq for TimedEvent<T> {}
-
 struct PlayerState {
     nb_events: u64,
     last_action_tick: u64,
@@ -75,30 +79,78 @@ impl PlayerState {
 }
 
 pub struct EventScheduler<T> {
-    events: BinaryHeap<TimedEvent<T>>,
+    queue: BinaryHeap<QueueKey>,
+    // Live events by id. Cancelling or re-keying an event leaves its old queue key
+    // behind, keys that don't match a live event's tick are skipped once popped
+    events: HashMap<Id, TimedEvent<T>>,
+    // Ids of the live events of each player, in scheduling order
+    player_events: HashMap<Id, Vec<Id>>,
     current_tick: u64,
     next_event_id: Id,
+    max_events: u64,
 }
 
 impl<T> EventScheduler<T> {
     pub fn new() -> Self {
+        Self::with_max_events(DEFAULT_MAX_QUEUED_COMMANDS)
+    }
+
+    /// Scheduler refusing to queue more than `max_events` events per player
+    pub fn with_max_events(max_events: u64) -> Self {
         Self {
-            events: BinaryHeap::new(),
+            queue: BinaryHeap::new(),
+            events: HashMap::new(),
+            player_events: HashMap::new(),
             current_tick: 0,
             next_event_id: 0,
+            max_events,
+        }
+    }
+
+    fn player_events(&self, player_id: Id) -> impl Iterator<Item = &TimedEvent<T>> {
+        self.player_events
+            .get(&player_id)
+            .into_iter()
+            .flatten()
+            .filter_map(|event_id| self.events.get(event_id))
+    }
+
+    fn insert(&mut self, event: TimedEvent<T>) -> Id {
+        let event_id = event.event_id;
+        self.queue.push(QueueKey {
+            expiration_tick: event.expiration_tick,
+            event_id,
+        });
+        self.player_events
+            .entry(event.player_id)
+            .or_default()
+            .push(event_id);
+        self.events.insert(event_id, event);
+        event_id
+    }
+
+    fn remove(&mut self, event_id: Id) -> Option<TimedEvent<T>> {
+        let event = self.events.remove(&event_id)?;
+        if let Some(ids) = self.player_events.get_mut(&event.player_id) {
+            ids.retain(|id| *id != event_id);
+            if ids.is_empty() {
+                self.player_events.remove(&event.player_id);
+            }
         }
+        Some(event)
     }
 
+    /// Client-initiated events queued by a player, and the tick its last event of any kind expires
     pub fn get_nb_events_by_player_id(&self, player_id: Id) -> (u64, u64) {
         let mut nb_events: u64 = 0;
         let mut last_action_tick = self.current_tick;
 
-        for event in self.events.iter() {
-            if event.player_id == player_id {
+        for event in self.player_events(player_id) {
+            if event.client_initiated {
                 nb_events += 1;
-                if event.expiration_tick > last_action_tick {
-                    last_action_tick = event.expiration_tick;
-                }
+            }
+            if event.expiration_tick > last_action_tick {
+                last_action_tick = event.expiration_tick;
             }
         }
 
@@ -116,6 +168,7 @@ impl<T> EventScheduler<T> {
             event_id,
             player_id,
             expiration_tick,
+            client_initiated: false,
         };
 
         debug!(
@@ -123,20 +176,37 @@ impl<T> EventScheduler<T> {
             event_id, player_id, expiration_tick
         );
 
-        self.events.push(event);
-        event_id
+        self.insert(event)
     }
 
-    pub fn schedule(&mut self, data: T, event_ticks: u64, player_id: Id) -> Id {
-        let event_id = self.next_event_id;
-        self.next_event_id += 1;
-
-        let (nb_events, last_tick) = self.get_nb_events_by_player_id(player_id);
-        if nb_events > MAX_SIMULTANEOUS_EVENTS {
+    /// Queues a client command after the player's other events, `None` when it's
+    /// refused past the queue limit
+    pub fn schedule(&mut self, data: T, event_ticks: u64, player_id: Id) -> Option<Id> {
+        let (nb_events, _) = self.get_nb_events_by_player_id(player_id);
+        if nb_events >= self.max_events {
             warn!("Client {} reached max nb_events", player_id);
-            return 0;
+            return None;
         }
+        Some(self.queue_after_player(data, event_ticks, player_id, true))
+    }
+
+    /// Queues a server event after the player's other events, it's never refused
+    /// and doesn't take one of the player's queue slots
+    pub fn schedule_internal(&mut self, data: T, event_ticks: u64, player_id: Id) -> Id {
+        self.queue_after_player(data, event_ticks, player_id, false)
+    }
 
+    fn queue_after_player(
+        &mut self,
+        data: T,
+        event_ticks: u64,
+        player_id: Id,
+        client_initiated: bool,
+    ) -> Id {
+        let event_id = self.next_event_id;
+        self.next_event_id += 1;
+
+        let (_, last_tick) = self.get_nb_events_by_player_id(player_id);
         let expiration_tick = last_tick + event_ticks;
 
         let event = TimedEvent {
@@ -144,40 +214,40 @@ impl<T> EventScheduler<T> {
             event_id,
             player_id,
             expiration_tick,
+            client_initiated,
         };
 
         //debug!(
         //    "Scheduled event #{} to execute at tick {}",
         //    event_id, expiration_tick
         //);
-        self.events.push(event);
-        event_id
+        self.insert(event)
     }
 
+    /// Delays (or advances) the queued events of a player, without moving anyone else's.
+    ///
+    /// Only that player's events get a new queue key, the old ones go stale
     pub fn shift_client_events(&mut self, player_id: Id, shift_ticks: i64) {
-        let mut all_events: Vec<TimedEvent<T>> = Vec::new();
-        let mut client_events: Vec<TimedEvent<T>> = Vec::new();
-
-        while let Some(event) = self.events.pop() {
-            if event.player_id == player_id {
-                client_events.push(event);
-            } else {
-                all_events.push(event);
-            }
-        }
-
-        for mut event in client_events {
+        let Some(ids) = self.player_events.get(&player_id) else {
+            return;
+        };
+        for event_id in ids {
+            let Some(event) = self.events.get_mut(event_id) else {
+                continue;
+            };
             let new_expiration_tick = if shift_ticks < 0 {
                 event.expiration_tick.saturating_sub(-shift_ticks as u64)
             } else {
                 event.expiration_tick.saturating_add(shift_ticks as u64)
             };
-            event.expiration_tick = new_expiration_tick.max(self.current_tick);
-            self.events.push(event);
-        }
-
-        for event in all_events {
-            self.events.push(event);
+            let new_expiration_tick = new_expiration_tick.max(self.current_tick);
+            if new_expiration_tick != event.expiration_tick {
+                event.expiration_tick = new_expiration_tick;
+                self.queue.push(QueueKey {
+                    expiration_tick: new_expiration_tick,
+                    event_id: *event_id,
+                });
+            }
         }
     }
 
@@ -194,17 +264,26 @@ impl<T> EventScheduler<T> {
     fn get_expired_events(&mut self) -> Vec<TimedEvent<T>> {
         let mut expired_events = Vec::new();
 
-        while let Some(event) = self.events.peek() {
-            if event.expiration_tick <= self.current_tick {
-                if let Some(event) = self.events.pop() {
-                    //debug!(
-                    //    "Event #{} executing at tick {}",
-                    //    event.event_id, self.current_tick
-                    //);
-                    expired_events.push(event);
-                }
-            } else {
+        while let Some(key) = self.queue.peek() {
+            if key.expiration_tick > self.current_tick {
+                break;
+            }
+            let Some(key) = self.queue.pop() else {
                 break;
+            };
+            let is_live = self
+                .events
+                .get(&key.event_id)
+                .is_some_and(|event| event.expiration_tick == key.expiration_tick);
+            if !is_live {
+                continue;
+            }
+            if let Some(event) = self.remove(key.event_id) {
+                //debug!(
+                //    "Event #{} executing at tick {}",
+                //    event.event_id, self.current_tick
+                //);
+                expired_events.push(event);
             }
         }
 
@@ -215,15 +294,9 @@ impl<T> EventScheduler<T> {
         expired_events
     }
 
+    /// Cancels a queued event, its queue key is dropped once it expires
     pub fn cancel(&mut self, event_id: Id) -> bool {
-        let index = self.events.iter().position(|e| e.event_id == event_id);
-
-        if index.is_some() {
-            let events = std::mem::take(&mut self.events);
-            self.events = events
-                .into_iter()
-                .filter(|e| e.event_id != event_id)
-                .collect();
+        if self.remove(event_id).is_some() {
             debug!("Cancelled event #{}", event_id);
             true
         } else {
@@ -231,6 +304,16 @@ impl<T> EventScheduler<T> {
         }
     }
 
+    /// Drops every queued event of a player, returns how many were queued
+    pub fn cancel_all_for_player(&mut self, player_id: Id) -> usize {
+        let ids = self.player_events.remove(&player_id).unwrap_or_default();
+        for event_id in &ids {
+            self.events.remove(event_id);
+        }
+        debug!("Cancelled {} events of player {}", ids.len(), player_id);
+        ids.len()
+    }
+
     pub fn current_tick(&self) -> u64 {
         self.current_tick
     }
@@ -239,12 +322,19 @@ impl<T> EventScheduler<T> {
         self.events.len()
     }
 
+    /// Returns the queued events of a player, in execution order
+    pub fn events_for_player(&self, player_id: Id) -> Vec<&TimedEvent<T>> {
+        let mut events: Vec<&TimedEvent<T>> = self.player_events(player_id).collect();
+        events.sort_by_key(|e| (e.expiration_tick, e.event_id));
+        events
+    }
+
     pub fn display_pending_events(&self) -> Vec<(u64, u64)>
     where
         T: Debug,
     {
-        let mut events: Vec<&TimedEvent<T>> = self.events.iter().collect();
-        events.sort_by_key(|e| e.expiration_tick);
+        let mut events: Vec<&TimedEvent<T>> = self.events.values().collect();
+        events.sort_by_key(|e| (e.expiration_tick, e.event_id));
         let mut result = Vec::new();
         for event in events {
             let remaining_ticks = event.expiration_tick.saturating_sub(self.current_tick);
@@ -259,3 +349,171 @@ impl<T> EventScheduler<T> {
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_for_player() {
+        let mut scheduler: EventScheduler<&str> = EventScheduler::new();
+        scheduler.schedule("look", 7, 1);
+        scheduler.schedule("forward", 7, 2);
+        scheduler.schedule("inventory", 1, 1);
+
+        let events = scheduler.events_for_player(1);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].data, "look");
+        assert_eq!(events[0].expiration_tick, 7);
+        assert_eq!(events[1].data, "inventory");
+        assert_eq!(events[1].expiration_tick, 8);
+        assert!(scheduler.events_for_player(3).is_empty());
+    }
+
+    #[test]
+    fn test_cancel_all_for_player_keeps_other_events_ordered() {
+        let mut scheduler: EventScheduler<&str> = EventScheduler::new();
+        scheduler.schedule("look", 7, 1);
+        scheduler.schedule("forward", 7, 2);
+        scheduler.schedule("inventory", 1, 1);
+        scheduler.schedule("right", 3, 2);
+        scheduler.schedule("take", 2, 3);
+
+        assert_eq!(scheduler.cancel_all_for_player(1), 2);
+        assert_eq!(scheduler.cancel_all_for_player(1), 0);
+        assert!(scheduler.events_for_player(1).is_empty());
+        assert_eq!(scheduler.pending_count(), 3);
+
+        let executed: Vec<&str> = scheduler
+            .tick_multiple(10)
+            .into_iter()
+            .map(|e| e.data)
+            .collect();
+        assert_eq!(executed, ["take", "forward", "right"]);
+    }
+
+    #[test]
+    fn test_cancelled_event_is_skipped() {
+        let mut scheduler: EventScheduler<&str> = EventScheduler::new();
+        let look = scheduler.schedule("look", 1, 1).unwrap();
+        scheduler.schedule("forward", 1, 2);
+
+        assert!(scheduler.cancel(look));
+        assert!(!scheduler.cancel(look));
+        assert_eq!(scheduler.pending_count(), 1);
+        assert!(scheduler.events_for_player(1).is_empty());
+
+        let executed: Vec<&str> = scheduler.tick().into_iter().map(|e| e.data).collect();
+        assert_eq!(executed, ["forward"]);
+        assert!(!scheduler.cancel(look));
+    }
+
+    #[test]
+    fn test_shift_client_events_leaves_other_players_untouched() {
+        let mut scheduler: EventScheduler<&str> = EventScheduler::new();
+        scheduler.schedule("look", 7, 1);
+        scheduler.schedule("forward", 7, 2);
+        scheduler.schedule("inventory", 1, 1);
+        scheduler.schedule("right", 3, 2);
+
+        let before: Vec<(Id, u64)> = scheduler
+            .events_for_player(2)
+            .iter()
+            .map(|e| (e.event_id, e.expiration_tick))
+            .collect();
+
+        scheduler.shift_client_events(1, 300);
+
+        let after: Vec<(Id, u64)> = scheduler
+            .events_for_player(2)
+            .iter()
+            .map(|e| (e.event_id, e.expiration_tick))
+            .collect();
+        assert_eq!(before, after);
+        let shifted: Vec<u64> = scheduler
+            .events_for_player(1)
+            .iter()
+            .map(|e| e.expiration_tick)
+            .collect();
+        assert_eq!(shifted, [307, 308]);
+
+        let executed: Vec<&str> = scheduler
+            .tick_multiple(10)
+            .into_iter()
+            .map(|e| e.data)
+            .collect();
+        assert_eq!(executed, ["forward", "right"]);
+    }
+
+    #[test]
+    fn test_same_tick_events_run_in_event_id_order() {
+        let mut scheduler: EventScheduler<&str> = EventScheduler::new();
+        let first = scheduler.schedule("take", 2, 2).unwrap();
+        let second = scheduler.schedule("take", 2, 1).unwrap();
+        let third = scheduler.force_schedule("set", 2, 2);
+
+        let executed: Vec<(Id, Id)> = scheduler
+            .tick_multiple(2)
+            .into_iter()
+            .map(|e| (e.event_id, e.player_id))
+            .collect();
+        assert_eq!(executed, [(first, 2), (second, 1), (third, 2)]);
+    }
+
+    #[test]
+    fn test_queue_limit_refuses_the_eleventh_event() {
+        let mut scheduler: EventScheduler<&str> = EventScheduler::new();
+        for _ in 0..9 {
+            scheduler.schedule("forward", 7, 1);
+        }
+
+        assert!(scheduler.schedule("tenth", 7, 1).is_some());
+        assert!(scheduler.schedule("eleventh", 7, 1).is_none());
+        assert_eq!(scheduler.events_for_player(1).len(), 10);
+        assert!(scheduler.schedule("other player", 7, 2).is_some());
+
+        let mut small: EventScheduler<&str> = EventScheduler::with_max_events(2);
+        // The first event gets id 0, it's still accepted
+        assert_eq!(small.schedule("look", 7, 1), Some(0));
+        assert!(small.schedule("look", 7, 1).is_some());
+        assert!(small.schedule("look", 7, 1).is_none());
+    }
+
+    #[test]
+    fn test_internal_events_do_not_use_queue_slots() {
+        let mut scheduler: EventScheduler<&str> = EventScheduler::new();
+        scheduler.force_schedule("phantom", 300, 1);
+        scheduler.schedule_internal("incantation end", 300, 1);
+
+        for _ in 0..10 {
+            assert!(scheduler.schedule("forward", 7, 1).is_some());
+        }
+        assert!(scheduler.schedule("forward", 7, 1).is_none());
+        assert_eq!(scheduler.get_nb_events_by_player_id(1), (10, 670));
+    }
+
+    #[test]
+    fn test_shift_only_requeues_the_shifted_player() {
+        let mut scheduler: EventScheduler<&str> = EventScheduler::new();
+        for player_id in 0..50 {
+            scheduler.schedule("look", 7, player_id);
+        }
+        scheduler.schedule("forward", 7, 3);
+        let queued = scheduler.queue.len();
+
+        scheduler.shift_client_events(3, 300);
+        assert_eq!(scheduler.queue.len(), queued + 2);
+        scheduler.shift_client_events(99, 300);
+        assert_eq!(scheduler.queue.len(), queued + 2);
+
+        // The stale keys are skipped, the shifted events run once, at their new tick
+        assert_eq!(scheduler.tick_multiple(14).len(), 49);
+        let shifted: Vec<(&str, u64)> = scheduler
+            .tick_multiple(300)
+            .into_iter()
+            .map(|e| (e.data, e.expiration_tick))
+            .collect();
+        assert_eq!(shifted, [("look", 307), ("forward", 314)]);
+        assert_eq!(scheduler.pending_count(), 0);
+    }
+}
@@ -31,7 +31,7 @@ impl fmt::Display for LookFormat<'_> {
         let cells = self.0;
         let mut formatted_cells = Vec::new();
 
-        for (player_count, resources) in cells {
+        for (player_count, egg_count, resources) in cells {
             let mut cell_elements = Vec::new();
 
             // Add players
@@ -39,19 +39,14 @@ impl fmt::Display for LookFormat<'_> {
                 cell_elements.push("player".to_string());
             }
 
+            // Add eggs, only counted when the server reports them
+            for _ in 0..*egg_count {
+                cell_elements.push("egg".to_string());
+            }
+
             // Add resources
-            let resource_names = [
-                ("food", Food),
-                ("linemate", Linemate),
-                ("deraumere", Deraumere),
-                ("sibur", Sibur),
-                ("mendiane", Mendiane),
-                ("phiras", Phiras),
-                ("thystame", Thystame),
-            ];
-
-            for &(name, index) in &resource_names {
-                for _ in 0..resources[index] {
+            for (name, amount) in resources.iter_named() {
+                for _ in 0..amount {
                     cell_elements.push(name.to_string());
                 }
             }
@@ -120,12 +115,12 @@ pub struct InventoryFormat<'a>(pub &'a Resources);
 
 impl fmt::Display for InventoryFormat<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let r = self.0;
-        write!(
-            f,
-            "[deraumere {}, linemate {}, mendiane {}, phiras {}, sibur {}, thystame {}, food {}]",
-            r[Deraumere], r[Linemate], r[Mendiane], r[Phiras], r[Sibur], r[Thystame], r[Food]
-        )
+        let items: Vec<String> = self
+            .0
+            .iter_named()
+            .map(|(name, amount)| format!("{} {}", name, amount))
+            .collect();
+        write!(f, "[{}]", items.join(", "))
     }
 }
 
@@ -133,17 +128,12 @@ pub struct ResourcesFormat<'a>(pub &'a Resources);
 
 impl fmt::Display for ResourcesFormat<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "{} {} {} {} {} {} {}",
-            self.0[Food],
-            self.0[Linemate],
-            self.0[Deraumere],
-            self.0[Sibur],
-            self.0[Mendiane],
-            self.0[Phiras],
-            self.0[Thystame]
-        )
+        let amounts: Vec<String> = self
+            .0
+            .iter_named()
+            .map(|(_, amount)| amount.to_string())
+            .collect();
+        write!(f, "{}", amounts.join(" "))
     }
 }
 
@@ -180,3 +170,41 @@ impl fmt::Display for ResourceFormat<'_> {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bct_format() {
+        let resources = Resources::builder()
+            .food(1)
+            .linemate(2)
+            .deraumere(3)
+            .sibur(4)
+            .mendiane(5)
+            .phiras(6)
+            .thystame(7)
+            .build();
+
+        assert_eq!(
+            BctFormat(&(UPosition::new(3, 4), resources.clone())).to_string(),
+            "bct 3 4 1 2 3 4 5 6 7"
+        );
+        assert_eq!(
+            InventoryFormat(&resources).to_string(),
+            "[food 1, linemate 2, deraumere 3, sibur 4, mendiane 5, phiras 6, thystame 7]"
+        );
+    }
+
+    #[test]
+    fn test_look_format_lists_eggs_after_players() {
+        let look = vec![
+            (1, 2, Resources::builder().food(1).build()),
+            (0, 0, Resources::new()),
+            (0, 1, Resources::new()),
+        ];
+
+        assert_eq!(LookFormat(&look).to_string(), "[player egg egg food,, egg]");
+    }
+}
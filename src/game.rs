@@ -0,0 +1,2873 @@
+use crate::constant::{
+    RELATIVE_DIRECTIONS, SATIETY_LOSS_PER_TICK, SERVER_EVENT_OWNER, WINNING_PLAYERS,
+};
+use crate::event::Event;
+use crate::event::EventScheduler;
+use crate::gui::{Gui, GuiBuilder};
+use crate::map::{EggPlacement, Map};
+use crate::pending::PendingClient;
+use crate::player::{Direction, Player, PlayerState};
+use crate::protocol::PendingResponse::{LogAs, Shared};
+use crate::protocol::{
+    AIAction, AIResponse, BctResponse, ClientSender, DebugTopic, EventType, GUIAction, GUIResponse,
+    GameEvent, HasId, Id, PendingAction, ServerResponse, SharedAction, SharedResponse, TeamType,
+};
+use crate::resources::{ElevationLevel, LEVEL_REQUIREMENTS, Resource, ResourceDensity, Resources};
+use crate::server::{ServerConfig, ServerError};
+use crate::sound::get_sound_direction;
+use crate::team::Team;
+use crate::vec2::{HasPosition, Position, Size, UPosition};
+use log::{debug, info, warn};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Keeps a tick frequency in [1, max_freq]: 0 would divide by zero and a huge
+/// one would make the tick loop spin
+fn clamp_frequency(freq: u64, max_freq: u64) -> u64 {
+    let clamped = freq.clamp(1, max_freq);
+    if clamped != freq {
+        warn!("Frequency {} clamped to {}", freq, clamped);
+    }
+    clamped
+}
+
+/// What happens once a team wins and `seg` is sent
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum EndAction {
+    /// The game stops advancing, every client stays connected
+    #[default]
+    Freeze,
+    /// The server stops, players get `dead` first
+    Stop,
+    /// After the given number of ticks players get `dead` and a fresh game starts,
+    /// guis stay connected and get a new snapshot
+    Restart(u64),
+}
+
+/// Game state and rules, driven either by the network [`Server`](crate::server::Server)
+/// or by a scripted [`Simulation`](crate::simulation::Simulation)
+pub struct Game {
+    map: Map,
+    teams: HashMap<Id, Team>,
+    pending_clients: HashMap<Id, PendingClient>,
+    clients: HashMap<Id, Player>,
+    guis: HashMap<Id, Gui>,
+    event_scheduler: EventScheduler<Event>,
+    frequency: u64,
+    max_freq: u64,
+    debug_commands: bool,
+    time_command: bool,
+    look_eggs: bool,
+    roster_command: bool,
+    admin_commands: bool,
+    idle_timeout: Option<u64>,
+    max_satiety: u64,
+    fork_cost: u64,
+    egg_lifetime: Option<u64>,
+    resource_density: ResourceDensity,
+    end_action: EndAction,
+    // Winning team and ticks elapsed since it won
+    game_over: Option<(Id, u64)>,
+    // Kept to lay out the next game on restart
+    config: ServerConfig,
+    rng: StdRng,
+}
+
+impl Game {
+    pub fn from_config(config: &ServerConfig) -> Result<Game, ServerError> {
+        config
+            .resource_density
+            .validate()
+            .map_err(|(resource, density)| ServerError::InvalidDensity(resource, density))?;
+        let (width, height) = (config.width as u64, config.height as u64);
+        if width == 0 || height == 0 || width * height > config.max_map_cells {
+            return Err(ServerError::InvalidMapSize(
+                width,
+                height,
+                config.max_map_cells,
+            ));
+        }
+
+        let mut teams: HashMap<Id, Team> = HashMap::new();
+
+        for (team_id, team_name) in config.teams.iter().enumerate() {
+            if team_name == "GRAPHIC" {
+                warn!("'GRAPHIC' can't be used as a team name and will be ignored");
+                continue;
+            }
+            teams.insert(
+                team_id as Id,
+                Team::new(
+                    team_id as Id,
+                    team_name.replace("\n", "_").replace("\r", "_"),
+                ),
+            );
+        }
+
+        let mut rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_os_rng(),
+        };
+        let map = Self::new_map(config, &teams, &mut rng);
+
+        Ok(Game {
+            map,
+            teams,
+            pending_clients: HashMap::new(),
+            clients: HashMap::new(),
+            guis: HashMap::new(),
+            event_scheduler: EventScheduler::with_max_events(config.max_queued_commands),
+            frequency: clamp_frequency(config.freq as u64, config.max_freq),
+            max_freq: config.max_freq,
+            debug_commands: config.debug_commands,
+            time_command: config.time_command,
+            look_eggs: config.look_eggs,
+            roster_command: config.roster_command,
+            admin_commands: config.admin_commands,
+            idle_timeout: config.idle_timeout,
+            max_satiety: config.max_satiety,
+            fork_cost: config.fork_cost,
+            egg_lifetime: config.egg_lifetime,
+            resource_density: config.resource_density.clone(),
+            end_action: config.end_action,
+            game_over: None,
+            config: config.clone(),
+            rng,
+        })
+    }
+
+    /// Empty map holding the initial eggs of every team
+    fn new_map(config: &ServerConfig, teams: &HashMap<Id, Team>, rng: &mut StdRng) -> Map {
+        let size = Size::new(config.width as u64, config.height as u64);
+        let mut map = if config.sparse_map {
+            Map::new_sparse(size)
+        } else {
+            Map::new(size)
+        };
+
+        let mut team_ids: Vec<Id> = teams.keys().copied().collect();
+        team_ids.sort();
+        match config.egg_placement {
+            EggPlacement::Random => {
+                for team_id in team_ids {
+                    map.spawn_eggs(team_id, config.clients_nb, rng);
+                }
+            }
+            EggPlacement::Balanced => {
+                map.spawn_eggs_balanced(&team_ids, config.clients_nb, rng);
+            }
+        }
+        map
+    }
+
+    /// Team that won the current game, if any
+    pub fn winner(&self) -> Option<Id> {
+        self.game_over.map(|(team_id, _)| team_id)
+    }
+
+    /// Whether the server should shut down, once a team won with `EndAction::Stop`
+    pub fn is_stopped(&self) -> bool {
+        self.game_over.is_some() && self.end_action == EndAction::Stop
+    }
+
+    /// Drops every client, their connections close once they've sent what was queued
+    pub fn disconnect_all(&mut self) {
+        self.pending_clients.clear();
+        self.clients.clear();
+        self.guis.clear();
+    }
+
+    pub fn map(&self) -> &Map {
+        &self.map
+    }
+
+    pub fn players(&self) -> impl Iterator<Item = &Player> {
+        self.clients.values()
+    }
+
+    pub fn player_mut(&mut self, player_id: Id) -> Option<&mut Player> {
+        self.clients.get_mut(&player_id)
+    }
+
+    pub fn team(&self, team_id: Id) -> Option<&Team> {
+        self.teams.get(&team_id)
+    }
+
+    /// Logged in players of a team, in no particular order
+    pub fn players_of_team(&self, team_id: Id) -> impl Iterator<Item = &Player> {
+        self.players()
+            .filter(move |player| player.team_id() == team_id)
+    }
+
+    /// How many players of the team are at each level, levels nobody reached are absent
+    pub fn count_by_level_for_team(&self, team_id: Id) -> HashMap<ElevationLevel, u64> {
+        let mut counts = HashMap::new();
+        for player in self.players_of_team(team_id) {
+            *counts.entry(player.level()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    pub fn current_tick(&self) -> u64 {
+        self.event_scheduler.current_tick()
+    }
+
+    /// Whether the player still has commands waiting to be executed
+    pub fn is_busy(&self, player_id: Id) -> bool {
+        !self.event_scheduler.events_for_player(player_id).is_empty()
+    }
+
+    pub fn add_pending_client(&mut self, pending_client: PendingClient) {
+        self.pending_clients
+            .insert(pending_client.id(), pending_client);
+    }
+
+    fn resource_targets(&self) -> Resources {
+        let nb_tiles = self.map.size().x() * self.map.size().y();
+        self.resource_density.targets(nb_tiles)
+    }
+
+    fn spawn_resources(&mut self) {
+        let size_x = self.map.size().x();
+        let size_y = self.map.size().y();
+        let targets = self.resource_targets();
+
+        for res in Resource::iter() {
+            if self.map.resources()[res] >= targets[res] {
+                continue;
+            }
+            let nb_missing = targets[res] - self.map.resources()[res];
+            (0..nb_missing).for_each(|_| {
+                let x = self.rng.random_range(0..size_x);
+                let y = self.rng.random_range(0..size_y);
+                let pos = UPosition::new(x, y);
+                self.map.add_resource(res, 1, pos, &mut self.guis);
+            });
+        }
+    }
+
+    /// Advances the game by exactly one tick, regardless of wall-clock time
+    pub async fn tick_once(&mut self) {
+        if let Some((_, elapsed)) = &mut self.game_over {
+            *elapsed += 1;
+            if let EndAction::Restart(delay) = self.end_action {
+                if *elapsed >= delay {
+                    self.restart();
+                }
+            }
+            return;
+        }
+        //info!("Updating current tick {:?}", self.event_scheduler.current_tick());
+        //info!("Updating server {}", self.clients.len());
+        //print!("\x1B[2J\x1B[1;1H"); // Effacer l'écran et replacer le curseur en haut à gauche
+        //println!("{}", self.map);
+        //println!("{:?}", self.clients);
+        //self.event_scheduler.display_pending_events();
+        self.spawn_resources();
+        let expired_events = self.event_scheduler.tick();
+        for timed_event in expired_events {
+            // do or ignore event if dead
+            match timed_event.data {
+                Event::Broadcast(str) => {
+                    let Some(emitter) = self.clients.get(&timed_event.player_id) else {
+                        continue;
+                    };
+                    let str = Arc::new(str);
+                    for receiver in self
+                        .clients
+                        .values()
+                        .filter(|receiver| receiver.id() != emitter.id())
+                    {
+                        let dir =
+                            get_sound_direction(emitter.into(), receiver.into(), self.map.size());
+                        let _ = receiver.send_to_client(ServerResponse::AI(AIResponse::Broadcast(
+                            dir,
+                            str.clone(),
+                        )));
+                    }
+                    //gui
+                    for (.., gui) in &self.guis {
+                        gui.notify(GUIResponse::Pbc(emitter.id(), str.clone()));
+                    }
+
+                    emitter
+                        .send_to_client(ServerResponse::AI(AIResponse::Shared(SharedResponse::Ok)));
+                }
+                Event::Forward => {
+                    let Some(emitter) = self.clients.get_mut(&timed_event.player_id) else {
+                        continue;
+                    };
+                    emitter
+                        .move_forward(&self.map.size())
+                        .send_to_client(ServerResponse::AI(AIResponse::Shared(SharedResponse::Ok)));
+                    //gui
+                    for (.., gui) in &self.guis {
+                        gui.notify(GUIResponse::Ppo(
+                            emitter.id(),
+                            emitter.position(),
+                            emitter.direction(),
+                        ));
+                    }
+                }
+                Event::Right => {
+                    let Some(emitter) = self.clients.get_mut(&timed_event.player_id) else {
+                        continue;
+                    };
+                    emitter.direction_mut().rotate_right();
+                    emitter
+                        .send_to_client(ServerResponse::AI(AIResponse::Shared(SharedResponse::Ok)));
+                    //gui
+                    for (.., gui) in &self.guis {
+                        gui.notify(GUIResponse::Ppo(
+                            emitter.id(),
+                            emitter.position(),
+                            emitter.direction(),
+                        ));
+                    }
+                }
+                Event::Left => {
+                    let Some(emitter) = self.clients.get_mut(&timed_event.player_id) else {
+                        continue;
+                    };
+                    emitter.direction_mut().rotate_left();
+                    emitter
+                        .send_to_client(ServerResponse::AI(AIResponse::Shared(SharedResponse::Ok)));
+
+                    //gui
+                    for (.., gui) in &self.guis {
+                        gui.notify(GUIResponse::Ppo(
+                            emitter.id(),
+                            emitter.position(),
+                            emitter.direction(),
+                        ));
+                    }
+                }
+                Event::Look => {
+                    let Some(emitter) = self.clients.get_mut(&timed_event.player_id) else {
+                        continue;
+                    };
+                    let visible_pos = emitter.get_visible_positions();
+                    let mut res = vec![];
+                    for cell_pos in visible_pos {
+                        let converted_pos = self.map.get_pos_signed(cell_pos);
+                        let nb_players_on_cell = self
+                            .clients
+                            .values()
+                            .filter(|client| client.position() == converted_pos)
+                            .count();
+                        let nb_eggs_on_cell = if self.look_eggs {
+                            self.map.eggs_at_pos(converted_pos).count()
+                        } else {
+                            0
+                        };
+                        let resources_on_cell =
+                            self.map.get_ressources_at_pos(converted_pos).clone();
+                        res.push((
+                            nb_players_on_cell as u64,
+                            nb_eggs_on_cell as u64,
+                            resources_on_cell,
+                        ));
+                    }
+                    self.clients
+                        .get_mut(&timed_event.player_id)
+                        .unwrap()
+                        .send_to_client(ServerResponse::AI(AIResponse::Look(res)));
+                }
+                Event::Inventory => {
+                    let Some(emitter) = self.clients.get_mut(&timed_event.player_id) else {
+                        continue;
+                    };
+                    emitter.send_to_client(ServerResponse::AI(AIResponse::Inventory(
+                        emitter.inventory(),
+                    )));
+                }
+                Event::ConnectNbr => {
+                    let Some(emitter) = self.clients.get_mut(&timed_event.player_id) else {
+                        continue;
+                    };
+                    emitter.send_to_client(ServerResponse::AI(AIResponse::ConnectNbr(
+                        self.map.nb_eggs_by_team(emitter.team_id()),
+                    )));
+                }
+                Event::Fork => {
+                    let Some(emitter) = self.clients.get_mut(&timed_event.player_id) else {
+                        continue;
+                    };
+                    let egg_id =
+                        self.map
+                            .lay_egg(emitter.team_id(), emitter.position(), emitter.id());
+                    if let Some(lifetime) = self.egg_lifetime {
+                        self.event_scheduler.force_schedule(
+                            Event::EggExpire(egg_id),
+                            lifetime,
+                            SERVER_EVENT_OWNER,
+                        );
+                    }
+
+                    //gui
+                    for (.., gui) in &self.guis {
+                        gui.notify(GUIResponse::Pfk(emitter.id()));
+                        gui.notify(GUIResponse::Enw(
+                            egg_id,
+                            Some(emitter.id()),
+                            emitter.position(),
+                        ));
+                    }
+
+                    emitter
+                        .send_to_client(ServerResponse::AI(AIResponse::Shared(SharedResponse::Ok)));
+                }
+                Event::Eject => {
+                    let Some(emitter) = self.clients.get_mut(&timed_event.player_id) else {
+                        continue;
+                    };
+
+                    let (pusher_pos, pusher_direction, pusher_id) =
+                        (emitter.position(), emitter.direction(), emitter.id());
+
+                    let players_on_same_pos: Vec<_> = self
+                        .clients
+                        .iter_mut()
+                        .filter_map(|(_, player)| {
+                            if player.position() == pusher_pos && player.id() != pusher_id {
+                                Some(player)
+                            } else {
+                                None
+                            }
+                        })
+                        .collect();
+
+                    let offset = match pusher_direction {
+                        Direction::North => (0, 1),
+                        Direction::East => (1, 0),
+                        Direction::South => (0, -1),
+                        Direction::West => (-1, 0),
+                    };
+                    let nb_pushed_players = players_on_same_pos.len();
+                    let new_pos = self
+                        .map
+                        .get_pos_with_offset(pusher_pos, Position::new(offset.0, offset.1));
+                    let direction: i8 = pusher_direction.into();
+                    for player in players_on_same_pos {
+                        player.position_mut().replace(new_pos);
+                        let pushed_dir: i8 = player.direction().into();
+                        let res = (direction - pushed_dir + 4).rem_euclid(4);
+                        let res = RELATIVE_DIRECTIONS[res as usize];
+                        //gui
+                        for (.., gui) in &self.guis {
+                            gui.notify(GUIResponse::Ppo(
+                                player.id(),
+                                player.position(),
+                                player.direction(),
+                            ));
+                        }
+                        player.send_to_client(ServerResponse::AI(AIResponse::Eject(res.into())));
+                    }
+                    let broken_eggs = self.map.break_eggs_at_pos(pusher_pos);
+                    let emitter = self.clients.get_mut(&timed_event.player_id).unwrap(); //safe since we know the player exists
+                    if nb_pushed_players > 0 || !broken_eggs.is_empty() {
+                        debug!(
+                            "Client {} broke {} eggs and pushed {} players",
+                            emitter.id(),
+                            broken_eggs.len(),
+                            nb_pushed_players
+                        );
+                        //gui
+                        for (.., gui) in &self.guis {
+                            gui.notify(GUIResponse::Pex(emitter.id()));
+                            for broken_egg in &broken_eggs {
+                                gui.notify(GUIResponse::Edi(broken_egg.id()));
+                            }
+                        }
+
+                        emitter.send_to_client(ServerResponse::AI(AIResponse::Shared(
+                            SharedResponse::Ok,
+                        )));
+                    } else {
+                        emitter.send_to_client(ServerResponse::AI(AIResponse::Shared(
+                            SharedResponse::Ko,
+                        )));
+                    }
+                }
+                // Contested takes go to the player who scheduled first, the others get `ko`,
+                // the map already sends the tile's `bct`
+                Event::Take(resource) => {
+                    let Some(emitter) = self.clients.get_mut(&timed_event.player_id) else {
+                        continue;
+                    };
+                    match self
+                        .map
+                        .del_resource(resource, 1, emitter.position(), &mut self.guis)
+                    {
+                        None => {
+                            emitter.send_to_client(ServerResponse::AI(AIResponse::Shared(
+                                SharedResponse::Ko,
+                            )));
+                        }
+                        Some(_) => {
+                            emitter.add_resource(resource, 1);
+
+                            //gui
+                            for (.., gui) in &self.guis {
+                                gui.notify(GUIResponse::Pgt(emitter.id(), resource));
+                                gui.notify(GUIResponse::Pin(
+                                    emitter.id(),
+                                    emitter.position(),
+                                    emitter.inventory(),
+                                ));
+                            }
+                            emitter.send_to_client(ServerResponse::AI(AIResponse::Shared(
+                                SharedResponse::Ok,
+                            )));
+                        }
+                    };
+                }
+                Event::Set(resource) => {
+                    let Some(emitter) = self.clients.get_mut(&timed_event.player_id) else {
+                        continue;
+                    };
+                    let res = emitter.del_resource(resource, 1);
+                    match res {
+                        None => {
+                            emitter.send_to_client(ServerResponse::AI(AIResponse::Shared(
+                                SharedResponse::Ko,
+                            )));
+                        }
+                        Some(resource) => {
+                            self.map
+                                .add_resource(resource, 1, emitter.position(), &mut self.guis);
+
+                            //gui
+                            for (.., gui) in &self.guis {
+                                gui.notify(GUIResponse::Pdr(emitter.id(), resource));
+                                gui.notify(GUIResponse::Pin(
+                                    emitter.id(),
+                                    emitter.position(),
+                                    emitter.inventory(),
+                                ));
+                            }
+                            emitter.send_to_client(ServerResponse::AI(AIResponse::Shared(
+                                SharedResponse::Ok,
+                            )));
+                        }
+                    }
+                }
+                Event::Incantation => {
+                    let Some(emitter) = self.clients.get_mut(&timed_event.player_id) else {
+                        continue;
+                    };
+                    let emitter_pos = emitter.position();
+                    let emitter_level = emitter.level();
+                    let emitter_id = emitter.id();
+                    debug!(
+                        "Incantation requirements for Client {}: {:?}",
+                        emitter.id(),
+                        LEVEL_REQUIREMENTS[&emitter_level]
+                    );
+                    let players_on_tile: Vec<Id> = self
+                        .clients
+                        .iter()
+                        .filter_map(|(id, player)| {
+                            if player.position() == emitter_pos
+                                && !player.is_incantating()
+                                && player.level() == emitter_level
+                            {
+                                Some(*id)
+                            } else {
+                                None
+                            }
+                        })
+                        .collect();
+
+                    let resources_on_tile: &Resources = self.map.get_ressources_at_pos(emitter_pos);
+                    let requirement = &LEVEL_REQUIREMENTS[&emitter_level];
+
+                    if players_on_tile.len() < requirement.needed_players()
+                        || !resources_on_tile.has_at_least(requirement.needed_resources())
+                    {
+                        let emitter = self.clients.get_mut(&timed_event.player_id).unwrap();
+                        emitter.send_to_client(ServerResponse::AI(AIResponse::Shared(
+                            SharedResponse::Ko,
+                        )));
+                        continue;
+                    }
+
+                    for id in &players_on_tile {
+                        let player = self.clients.get_mut(id).unwrap();
+                        *player.state_mut() = PlayerState::Incantating;
+                        player.send_to_client(ServerResponse::AI(AIResponse::Incantating));
+                        // Participants are frozen for the ritual, their queued commands
+                        // (a pending `Look` included) run once it's over
+                        if *id != emitter_id {
+                            self.event_scheduler.shift_client_events(*id, 300);
+                            self.event_scheduler
+                                .force_schedule(Event::Phantom, 300, *id);
+                        }
+                        debug!("Player {} is now {:?}", id, player.state_mut());
+                    }
+
+                    let emitter = self.clients.get_mut(&timed_event.player_id).unwrap();
+
+                    //gui
+                    for (.., gui) in &self.guis {
+                        gui.notify(GUIResponse::Pic(
+                            emitter_pos,
+                            emitter.level(),
+                            players_on_tile.clone(),
+                        ));
+                    }
+
+                    let new_event =
+                        Event::IncantationEnd(players_on_tile, requirement, emitter.position());
+                    self.event_scheduler
+                        .schedule_internal(new_event, 300, emitter.id());
+                }
+                Event::IncantationEnd(players_incantating, requirement, incantation_pos) => {
+                    let mut players_still_on_tile: Vec<Id> = vec![];
+
+                    for id in &players_incantating {
+                        if let Some(player) = self.clients.get_mut(id) {
+                            if player.is_incantating() && player.position() == incantation_pos {
+                                *player.state_mut() = PlayerState::Idle;
+                                players_still_on_tile.push(*id);
+                            }
+                        }
+                    }
+
+                    if players_still_on_tile.len() < requirement.needed_players()
+                        || !self.map.try_consume(
+                            incantation_pos,
+                            requirement.needed_resources(),
+                            &mut self.guis,
+                        )
+                    {
+                        //gui
+                        for (.., gui) in &self.guis {
+                            gui.notify(GUIResponse::Pie(incantation_pos, false));
+                        }
+
+                        for id in &players_incantating {
+                            if let Some(client) = self.clients.get_mut(id) {
+                                client.send_to_client(ServerResponse::AI(AIResponse::Shared(
+                                    SharedResponse::Ko,
+                                )));
+                            }
+                        }
+                        continue;
+                    }
+                    for id in &players_still_on_tile {
+                        let client = self.clients.get_mut(id).unwrap();
+                        *client.level_mut() = client.level().upgrade();
+                        client.send_to_client(ServerResponse::AI(AIResponse::LevelUp(
+                            client.level(),
+                        )));
+
+                        //gui
+                        for (.., gui) in &self.guis {
+                            gui.notify(GUIResponse::Plv(client.id(), client.level()));
+                        }
+                    }
+
+                    //gui
+                    for (.., gui) in &self.guis {
+                        gui.notify(GUIResponse::Pie(incantation_pos, true));
+                    }
+                    debug!(
+                        "Incantation successful for Clients : {:?}",
+                        players_still_on_tile
+                    );
+                }
+                Event::Ko => {
+                    if let Some(client) = self.clients.get_mut(&timed_event.player_id) {
+                        client.send_to_client(ServerResponse::AI(AIResponse::Shared(
+                            SharedResponse::Ko,
+                        )));
+                    } else {
+                        continue;
+                    }
+                }
+                Event::Time => {
+                    let Some(emitter) = self.clients.get(&timed_event.player_id) else {
+                        continue;
+                    };
+                    emitter.send_to_client(ServerResponse::AI(AIResponse::Time(
+                        self.event_scheduler.current_tick(),
+                        self.frequency,
+                    )));
+                }
+                Event::EggExpire(egg_id) => {
+                    // Eggs that hatched or broke in the meantime are already gone
+                    if self.map.remove_egg(egg_id).is_some() {
+                        for (.., gui) in &self.guis {
+                            gui.notify(GUIResponse::Edi(egg_id));
+                        }
+                    }
+                }
+                Event::Phantom => continue,
+            }
+        }
+        self.disconnect_idle_players();
+        self.reduce_satiety();
+        self.check_winner();
+    }
+
+    /// Ends the game once a team has enough level 8 players
+    fn check_winner(&mut self) {
+        let mut team_ids: Vec<Id> = self.teams.keys().copied().collect();
+        team_ids.sort();
+        let Some(winner) = team_ids.into_iter().find(|team_id| {
+            self.count_by_level_for_team(*team_id)
+                .get(&ElevationLevel::Level8)
+                .is_some_and(|count| *count >= WINNING_PLAYERS)
+        }) else {
+            return;
+        };
+
+        let team_name = self.teams[&winner].name().to_string();
+        info!(
+            "Team {} won, ending the game ({:?})",
+            team_name, self.end_action
+        );
+        self.game_over = Some((winner, 0));
+        for (.., gui) in &self.guis {
+            gui.notify(GUIResponse::Seg(team_name.clone()));
+        }
+        if self.end_action == EndAction::Stop {
+            self.kill_all_players();
+        }
+    }
+
+    fn kill_all_players(&mut self) {
+        let ids: Vec<Id> = self.clients.keys().copied().collect();
+        for id in ids {
+            self.kill_player(id);
+        }
+    }
+
+    /// Starts a fresh game, pending clients and guis stay connected
+    fn restart(&mut self) {
+        info!("Restarting the game");
+        self.kill_all_players();
+        self.event_scheduler = EventScheduler::with_max_events(self.config.max_queued_commands);
+        self.map = Self::new_map(&self.config, &self.teams, &mut self.rng);
+        self.game_over = None;
+        for gui in self.guis.values() {
+            self.send_snapshot(gui);
+        }
+    }
+
+    /// Removes a player from the game and its queued events, notifying it and the guis
+    fn kill_player(&mut self, id: Id) {
+        let Some(player) = self.clients.remove(&id) else {
+            return;
+        };
+        // An incantation it started still ends on time for the other participants
+        let current_tick = self.event_scheduler.current_tick();
+        let incantation_ends: Vec<(Event, u64)> = self
+            .event_scheduler
+            .events_for_player(id)
+            .into_iter()
+            .filter_map(|event| match &event.data {
+                Event::IncantationEnd(players, requirement, pos) => Some((
+                    Event::IncantationEnd(players.clone(), requirement, *pos),
+                    event.expiration_tick - current_tick,
+                )),
+                _ => None,
+            })
+            .collect();
+        self.event_scheduler.cancel_all_for_player(id);
+        for (end, ticks) in incantation_ends {
+            self.event_scheduler
+                .force_schedule(end, ticks, SERVER_EVENT_OWNER);
+        }
+        player.send_to_client(ServerResponse::AI(AIResponse::Dead));
+        for (.., gui) in &self.guis {
+            gui.notify(GUIResponse::Pdi(id));
+        }
+    }
+
+    fn disconnect_idle_players(&mut self) {
+        let Some(idle_timeout) = self.idle_timeout else {
+            return;
+        };
+        let current_tick = self.event_scheduler.current_tick();
+        let idle_players: Vec<Id> = self
+            .clients
+            .values()
+            .filter(|player| current_tick.saturating_sub(player.last_command_tick()) > idle_timeout)
+            .map(|player| player.id())
+            .collect();
+
+        for id in idle_players {
+            info!(
+                "Client {} was idle for more than {} ticks",
+                id, idle_timeout
+            );
+            self.kill_player(id);
+        }
+    }
+
+    /// Makes every player hungrier, guis get a `pin` for each player that ate
+    pub fn reduce_satiety(&mut self) {
+        for (id, client) in self.clients.iter_mut() {
+            let food = client.inventory()[Resource::Food];
+            if client.reduce_satiety(SATIETY_LOSS_PER_TICK) == 0 {
+                client.send_to_client(ServerResponse::AI(AIResponse::Dead));
+                info!("Client {} is dead", id);
+            }
+            if client.inventory()[Resource::Food] != food {
+                for (.., gui) in &self.guis {
+                    gui.notify(GUIResponse::Pin(
+                        client.id(),
+                        client.position(),
+                        client.inventory(),
+                    ));
+                }
+            }
+        }
+    }
+
+    pub async fn process_events(&mut self, event: EventType) {
+        debug!("Event {:?}", event);
+        match event {
+            EventType::AI(GameEvent { id, action }) => {
+                self.handle_ai_events((id, action)).await;
+            }
+            EventType::GUI(GameEvent { id, action }) => {
+                self.handle_gui_events((id, action)).await;
+            }
+            EventType::Pending(GameEvent { id, action }) => {
+                self.handle_pending_events((id, action)).await;
+            }
+        }
+    }
+
+    async fn handle_pending_events(&mut self, (id, action): (Id, PendingAction)) {
+        let Some(client) = self.pending_clients.get_mut(&id) else {
+            warn!(
+                "This client is not pending anymore : {}, cancelled event {:?}",
+                id, action
+            );
+            return;
+        };
+
+        fn send_ko(client: &mut impl ClientSender) {
+            client.send_to_client(ServerResponse::Pending(Shared(SharedResponse::Ko)));
+        }
+
+        match action {
+            PendingAction::Shared(SharedAction::Disconnected) => {
+                self.pending_clients.remove_entry(&id);
+                info!("Pending client: {} disconnected", id);
+            }
+            PendingAction::Shared(
+                SharedAction::InvalidAction | SharedAction::InvalidParameters,
+            ) => unreachable!(),
+            PendingAction::Shared(SharedAction::ReachedTakeLimit) => {
+                warn!("Pending client: {} sent too much data", id);
+                send_ko(client);
+            }
+            PendingAction::Shared(SharedAction::InvalidEncoding) => {
+                warn!("Pending client: {} uses invalid encoding", id);
+                send_ko(client);
+            }
+            PendingAction::Login(team_name) => {
+                // Reserved: a team can't be named GRAPHIC, and once logged in an AI
+                // sending it again only gets `ko` for an unknown command
+                if team_name == "GRAPHIC" {
+                    let pending_client = self.pending_clients.remove(&id).unwrap();
+
+                    let new_gui = GuiBuilder::new()
+                        .pending_client(pending_client)
+                        .build()
+                        .unwrap();
+                    new_gui.send_to_client(ServerResponse::Pending(LogAs(TeamType::Graphic)));
+                    self.send_snapshot(&new_gui);
+                    self.guis.insert(id, new_gui);
+                    return;
+                }
+
+                // A refused login keeps the client pending so it can retry with another team
+                let Some(team) = self.teams.values().find(|team| team.name() == team_name) else {
+                    warn!("Client {} can't login: no team named '{}'", id, team_name);
+                    send_ko(client);
+                    return;
+                };
+
+                if self.map.nb_eggs_by_team(team.id()) == 0 {
+                    warn!(
+                        "Client {} can't login: team '{}' has no eggs",
+                        id, team_name
+                    );
+                    send_ko(client);
+                    return;
+                }
+
+                let egg = self.map.drop_egg(team.id(), &mut self.rng).unwrap();
+                let pending_client = self.pending_clients.remove(&id).unwrap();
+
+                let player_builder = Player::builder()
+                    .team(team.id())
+                    .pending_client(pending_client)
+                    .position(egg.position())
+                    .direction(self.rng.random())
+                    .max_satiety(self.max_satiety)
+                    .last_command_tick(self.event_scheduler.current_tick());
+
+                let player = player_builder.build().unwrap();
+                player.send_to_client(ServerResponse::Pending(LogAs(TeamType::IA(
+                    self.map.nb_eggs_by_team(team.id()),
+                    self.map.size(),
+                ))));
+
+                // gui
+                for (.., gui) in &self.guis {
+                    gui.notify(GUIResponse::Pnw(
+                        player.id(),
+                        player.position(),
+                        player.direction(),
+                        player.level(),
+                        team_name.clone(),
+                    ));
+                    gui.notify(GUIResponse::Ebo(egg.id()));
+                }
+
+                self.clients.insert(player.id(), player);
+            }
+        }
+    }
+
+    /// Contents of every tile in row-major order.
+    ///
+    /// Only the resources are copied here, the `bct` lines are formatted by the
+    /// gui's connection task, off the game loop
+    fn mct(&self) -> Vec<BctResponse> {
+        let size = self.map.size();
+        let mut tiles = Vec::with_capacity((size.x() * size.y()) as usize);
+        tiles.extend(
+            self.map
+                .cells_with_positions()
+                .map(|(pos, cell)| (pos, cell.ressources().clone())),
+        );
+        tiles
+    }
+
+    /// Sends the current state of the game to a newly connected gui.
+    ///
+    /// `msz` always comes first, and the gui is only registered once the snapshot
+    /// is queued, so no player update can reach it before the map dimensions
+    fn send_snapshot(&self, gui: &Gui) {
+        gui.send_to_client(ServerResponse::Gui(GUIResponse::Msz(self.map.size())));
+        gui.send_to_client(ServerResponse::Gui(GUIResponse::Sgt(self.frequency())));
+        gui.send_to_client(ServerResponse::Gui(GUIResponse::Mct(self.mct())));
+        gui.send_to_client(ServerResponse::Gui(GUIResponse::Tna(self.team_names())));
+
+        for player in self.players_by_id() {
+            gui.send_to_client(ServerResponse::Gui(self.pnw(player)));
+            gui.send_to_client(ServerResponse::Gui(GUIResponse::Pin(
+                player.id(),
+                player.position(),
+                player.inventory(),
+            )));
+        }
+    }
+
+    fn players_by_id(&self) -> Vec<&Player> {
+        let mut players: Vec<&Player> = self.clients.values().collect();
+        players.sort_by_key(|player| player.id());
+        players
+    }
+
+    fn pnw(&self, player: &Player) -> GUIResponse {
+        GUIResponse::Pnw(
+            player.id(),
+            player.position(),
+            player.direction(),
+            player.level(),
+            self.teams[&player.team_id()].name().to_string(),
+        )
+    }
+
+    /// Ticks per second, as last set by `sst`. Stored as the integer that was
+    /// requested, `sgt` never derives it back from the tick interval
+    pub fn frequency(&self) -> u64 {
+        self.frequency
+    }
+
+    fn team_names(&self) -> Vec<String> {
+        self.teams
+            .iter()
+            .map(|(.., team_name)| team_name.name().to_string())
+            .collect::<Vec<_>>()
+    }
+
+    async fn handle_ai_events(&mut self, (id, action): (Id, AIAction)) {
+        if let Some(player) = self.clients.get_mut(&id) {
+            *player.last_command_tick_mut() = self.event_scheduler.current_tick();
+        }
+        match action {
+            AIAction::Shared(shared) => match shared {
+                // No reconnection: the player and the egg it hatched from are gone,
+                // logging in again creates a new player from another egg
+                SharedAction::Disconnected => {
+                    if self.clients.remove(&id).is_some() {
+                        for (.., gui) in &self.guis {
+                            gui.notify(GUIResponse::Pdi(id));
+                        }
+                    }
+                }
+                // `ko` is the server's answer, it's always sent and takes no queue slot
+                SharedAction::InvalidParameters => {
+                    debug!("Client {} sent a command with bad parameters", id);
+                    self.event_scheduler.schedule_internal(Event::Ko, 0, id);
+                }
+                SharedAction::InvalidAction
+                | SharedAction::ReachedTakeLimit
+                | SharedAction::InvalidEncoding => {
+                    self.event_scheduler.schedule_internal(Event::Ko, 0, id);
+                }
+            },
+            AIAction::Action(action) => match action {
+                event @ (Event::Broadcast(_)
+                | Event::Forward
+                | Event::Right
+                | Event::Left
+                | Event::Look
+                | Event::Take(_)
+                | Event::Set(_)
+                | Event::Eject) => {
+                    self.event_scheduler.schedule(event, 7, id);
+                }
+                event @ Event::Inventory => {
+                    self.event_scheduler.schedule(event, 1, id);
+                }
+                event @ Event::ConnectNbr => {
+                    self.event_scheduler.schedule(event, 0, id);
+                }
+                Event::Time if !self.time_command => {
+                    self.event_scheduler.schedule_internal(Event::Ko, 0, id);
+                }
+                event @ Event::Time => {
+                    self.event_scheduler.schedule(event, 0, id);
+                }
+                event @ Event::Fork => {
+                    self.event_scheduler.schedule(event, self.fork_cost, id);
+                }
+                event @ Event::Incantation => {
+                    self.event_scheduler.schedule(event, 0, id);
+                }
+                _ => {
+                    unreachable!()
+                }
+            },
+        }
+    }
+
+    async fn handle_gui_events(&mut self, (id, action): (Id, GUIAction)) {
+        match action {
+            GUIAction::Shared(shared) => match shared {
+                SharedAction::Disconnected => {
+                    self.guis.remove(&id);
+                }
+                SharedAction::InvalidAction
+                | SharedAction::ReachedTakeLimit
+                | SharedAction::InvalidEncoding => {
+                    if let Some(emitter) = self.guis.get_mut(&id) {
+                        emitter.send_to_client(ServerResponse::Gui(GUIResponse::Shared(
+                            SharedResponse::Ko,
+                        )));
+                    }
+                }
+                SharedAction::InvalidParameters => {
+                    if let Some(emitter) = self.guis.get_mut(&id) {
+                        emitter.send_to_client(ServerResponse::Gui(GUIResponse::Sbp));
+                    }
+                }
+            },
+            GUIAction::Msz => {
+                if let Some(emitter) = self.guis.get_mut(&id) {
+                    emitter.send_to_client(ServerResponse::Gui(GUIResponse::Msz(self.map.size())));
+                }
+            }
+            GUIAction::Bct(pos) => {
+                if let Some(emitter) = self.guis.get_mut(&id) {
+                    // Gameplay wraps around the torus but `bct` does not: out-of-map
+                    // coordinates are a bad parameter, as the protocol answers `sbp`
+                    let Some(cell) = self.map.get(pos) else {
+                        emitter.send_to_client(ServerResponse::Gui(GUIResponse::Sbp));
+                        return;
+                    };
+                    emitter.send_to_client(ServerResponse::Gui(GUIResponse::Bct((
+                        pos,
+                        cell.ressources().clone(),
+                    ))));
+                }
+            }
+            GUIAction::Mct => {
+                if let Some(emitter) = self.guis.get(&id) {
+                    emitter.send_to_client(ServerResponse::Gui(GUIResponse::Mct(self.mct())));
+                }
+            }
+            GUIAction::Tna => {
+                if let Some(emitter) = self.guis.get(&id) {
+                    emitter
+                        .send_to_client(ServerResponse::Gui(GUIResponse::Tna(self.team_names())));
+                }
+            }
+            GUIAction::Ppo(player_id) => {
+                if let Some(emitter) = self.guis.get_mut(&id) {
+                    if let Some(player) = self.clients.get(&player_id) {
+                        emitter.send_to_client(ServerResponse::Gui(GUIResponse::Ppo(
+                            player_id,
+                            player.position(),
+                            player.direction(),
+                        )));
+                    } else {
+                        emitter.send_to_client(ServerResponse::Gui(GUIResponse::Sbp));
+                    }
+                }
+            }
+            GUIAction::Plv(player_id) => {
+                if let Some(emitter) = self.guis.get_mut(&id) {
+                    if let Some(player) = self.clients.get(&player_id) {
+                        emitter.send_to_client(ServerResponse::Gui(GUIResponse::Plv(
+                            player_id,
+                            player.level(),
+                        )));
+                    } else {
+                        emitter.send_to_client(ServerResponse::Gui(GUIResponse::Sbp));
+                    }
+                }
+            }
+            GUIAction::Pin(player_id) => {
+                if let Some(emitter) = self.guis.get_mut(&id) {
+                    if let Some(player) = self.clients.get(&player_id) {
+                        emitter.send_to_client(ServerResponse::Gui(GUIResponse::Pin(
+                            player_id,
+                            player.position(),
+                            player.inventory(),
+                        )));
+                    } else {
+                        emitter.send_to_client(ServerResponse::Gui(GUIResponse::Sbp));
+                    }
+                }
+            }
+            GUIAction::Sgt => {
+                if let Some(emitter) = self.guis.get(&id) {
+                    emitter.send_to_client(ServerResponse::Gui(GUIResponse::Sgt(self.frequency())));
+                }
+            }
+            GUIAction::Sst(freq) => {
+                if let Some(emitter) = self.guis.get_mut(&id) {
+                    let freq = clamp_frequency(freq, self.max_freq);
+                    self.frequency = freq;
+                    emitter.send_to_client(ServerResponse::Gui(GUIResponse::Sst(freq)));
+                    for (.., gui) in &self.guis {
+                        gui.notify(GUIResponse::Sgt(freq));
+                    }
+                }
+            }
+            GUIAction::Subscribe(category, subscribed) => {
+                if let Some(emitter) = self.guis.get_mut(&id) {
+                    emitter.set_subscribed(category, subscribed);
+                    emitter.send_to_client(ServerResponse::Gui(GUIResponse::Subscribe(
+                        category, subscribed,
+                    )));
+                }
+            }
+            GUIAction::Pls => {
+                if let Some(emitter) = self.guis.get(&id) {
+                    if !self.roster_command {
+                        emitter.send_to_client(ServerResponse::Gui(GUIResponse::Shared(
+                            SharedResponse::Ko,
+                        )));
+                        return;
+                    }
+                    for player in self.players_by_id() {
+                        emitter.send_to_client(ServerResponse::Gui(self.pnw(player)));
+                    }
+                }
+            }
+            GUIAction::Egl => {
+                if let Some(emitter) = self.guis.get(&id) {
+                    if !self.roster_command {
+                        emitter.send_to_client(ServerResponse::Gui(GUIResponse::Shared(
+                            SharedResponse::Ko,
+                        )));
+                        return;
+                    }
+                    for egg in self.map.eggs() {
+                        emitter.send_to_client(ServerResponse::Gui(GUIResponse::Enw(
+                            egg.id(),
+                            egg.layer(),
+                            egg.position(),
+                        )));
+                    }
+                }
+            }
+            GUIAction::Kill(player_id) => {
+                let Some(emitter) = self.guis.get(&id) else {
+                    return;
+                };
+                if !self.admin_commands {
+                    emitter.send_to_client(ServerResponse::Gui(GUIResponse::Shared(
+                        SharedResponse::Ko,
+                    )));
+                } else if !self.clients.contains_key(&player_id) {
+                    emitter.send_to_client(ServerResponse::Gui(GUIResponse::Sbp));
+                } else {
+                    info!("Gui {} killed player {}", id, player_id);
+                    self.kill_player(player_id);
+                }
+            }
+            GUIAction::Setr(pos, amounts) => {
+                let Some(emitter) = self.guis.get(&id) else {
+                    return;
+                };
+                if !self.admin_commands {
+                    emitter.send_to_client(ServerResponse::Gui(GUIResponse::Shared(
+                        SharedResponse::Ko,
+                    )));
+                } else if self.map.get(pos).is_none() {
+                    emitter.send_to_client(ServerResponse::Gui(GUIResponse::Sbp));
+                } else {
+                    self.map.set_resources(pos, &amounts, &mut self.guis);
+                }
+            }
+            GUIAction::Tp(player_id, pos) => {
+                let Some(emitter) = self.guis.get(&id) else {
+                    return;
+                };
+                if !self.admin_commands {
+                    emitter.send_to_client(ServerResponse::Gui(GUIResponse::Shared(
+                        SharedResponse::Ko,
+                    )));
+                    return;
+                }
+                let Some(player) = self.clients.get_mut(&player_id) else {
+                    emitter.send_to_client(ServerResponse::Gui(GUIResponse::Sbp));
+                    return;
+                };
+                if self.map.get(pos).is_none() {
+                    emitter.send_to_client(ServerResponse::Gui(GUIResponse::Sbp));
+                    return;
+                }
+                *player.position_mut() = pos;
+                for (.., gui) in &self.guis {
+                    gui.notify(GUIResponse::Ppo(player_id, pos, player.direction()));
+                }
+            }
+            GUIAction::Dbg(topic) => {
+                if let Some(emitter) = self.guis.get(&id) {
+                    if !self.debug_commands {
+                        emitter.send_to_client(ServerResponse::Gui(GUIResponse::Shared(
+                            SharedResponse::Ko,
+                        )));
+                        return;
+                    }
+                    let lines = match topic {
+                        DebugTopic::Scheduler => self.scheduler_dump(),
+                        DebugTopic::Map => self.map_dump(),
+                    };
+                    emitter.send_to_client(ServerResponse::Gui(GUIResponse::Dbg(lines)));
+                }
+            }
+        }
+    }
+
+    /// Logs the ASCII map in a single record, the gui only gets the player positions
+    fn map_dump(&self) -> Vec<String> {
+        let mut players: Vec<&Player> = self.clients.values().collect();
+        players.sort_by_key(|player| player.id());
+        let lines: Vec<String> = players
+            .iter()
+            .map(|player| {
+                format!(
+                    "player #{} at {} {} facing {:?}",
+                    player.id(),
+                    player.position().x(),
+                    player.position().y(),
+                    player.direction()
+                )
+            })
+            .collect();
+
+        info!(
+            "Map at tick {}:\n{}{}",
+            self.event_scheduler.current_tick(),
+            self.map,
+            lines.join("\n")
+        );
+        lines
+    }
+
+    fn scheduler_dump(&self) -> Vec<String> {
+        let current_tick = self.event_scheduler.current_tick();
+        let mut lines = vec![
+            format!("tick {}", current_tick),
+            format!("pending {}", self.event_scheduler.pending_count()),
+        ];
+
+        let mut players: Vec<&Player> = self.clients.values().collect();
+        players.sort_by_key(|player| player.id());
+        for player in players {
+            let player_id = &player.id();
+            let events = self
+                .event_scheduler
+                .events_for_player(*player_id)
+                .iter()
+                .map(|e| {
+                    format!(
+                        "{:?}@+{}",
+                        e.data,
+                        e.expiration_tick.saturating_sub(current_tick)
+                    )
+                })
+                .collect::<Vec<_>>();
+            lines.push(format!(
+                "player #{} satiety {}/{} queued {} [{}]",
+                player_id,
+                player.satiety(),
+                player.max_satiety(),
+                events.len(),
+                events.join(", ")
+            ));
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constant::REFILL_PER_FOOD;
+    use crate::formater::LookFormat;
+    use crate::handler::ai::AiHandler;
+    use crate::handler::command::CommandHandler;
+    use crate::protocol::GuiCategory;
+    use tokio::sync::mpsc;
+
+    fn test_config() -> ServerConfig {
+        ServerConfig::new(
+            "127.0.0.1".to_string(),
+            0,
+            10,
+            10,
+            vec!["team1".to_string(), "team2".to_string()],
+            4,
+            100,
+        )
+    }
+
+    fn add_player(game: &mut Game, id: Id, team: Id) -> mpsc::Receiver<ServerResponse> {
+        let (tx, rx) = mpsc::channel(128);
+        let player = Player::builder()
+            .id(id)
+            .team(team)
+            .client_tx(tx)
+            .build()
+            .unwrap();
+        game.clients.insert(id, player);
+        rx
+    }
+
+    fn add_pending(game: &mut Game, id: Id) -> mpsc::Receiver<ServerResponse> {
+        let (client_tx, rx) = mpsc::channel(128);
+        game.pending_clients.insert(
+            id,
+            PendingClient {
+                client_id: id,
+                client_tx,
+            },
+        );
+        rx
+    }
+
+    async fn login(game: &mut Game, id: Id, team_name: &str) {
+        game.handle_pending_events((id, PendingAction::Login(team_name.to_string())))
+            .await;
+    }
+
+    fn add_gui(game: &mut Game, id: Id) -> mpsc::Receiver<ServerResponse> {
+        let (client_tx, rx) = mpsc::channel(4096);
+        let gui = GuiBuilder::new()
+            .pending_client(PendingClient {
+                client_id: id,
+                client_tx,
+            })
+            .build()
+            .unwrap();
+        game.guis.insert(id, gui);
+        rx
+    }
+
+    fn drain(rx: &mut mpsc::Receiver<ServerResponse>) -> Vec<ServerResponse> {
+        let mut responses = Vec::new();
+        while let Ok(response) = rx.try_recv() {
+            responses.push(response);
+        }
+        responses
+    }
+
+    #[tokio::test]
+    async fn test_idle_player_is_disconnected() {
+        let mut game = Game::from_config(&test_config().idle_timeout(Some(5))).unwrap();
+        let mut idle_rx = add_player(&mut game, 1, 0);
+        let mut active_rx = add_player(&mut game, 2, 0);
+        let mut gui_rx = add_gui(&mut game, 100);
+
+        for _ in 0..6 {
+            game.handle_ai_events((2, AIAction::Action(Event::Inventory)))
+                .await;
+            game.tick_once().await;
+        }
+
+        assert!(!game.clients.contains_key(&1));
+        assert!(game.clients.contains_key(&2));
+        assert!(
+            drain(&mut idle_rx)
+                .iter()
+                .any(|r| matches!(r, ServerResponse::AI(AIResponse::Dead)))
+        );
+        assert!(
+            !drain(&mut active_rx)
+                .iter()
+                .any(|r| matches!(r, ServerResponse::AI(AIResponse::Dead)))
+        );
+        let pdi_count = drain(&mut gui_rx)
+            .iter()
+            .filter(|r| matches!(r, ServerResponse::Gui(GUIResponse::Pdi(1))))
+            .count();
+        assert_eq!(pdi_count, 1);
+
+        game.handle_ai_events((1, AIAction::Shared(SharedAction::Disconnected)))
+            .await;
+        assert!(drain(&mut gui_rx).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_login_spawns_player_on_consumed_egg() {
+        let mut game = Game::from_config(&test_config()).unwrap();
+        let _rx = add_pending(&mut game, 1);
+        let eggs_before: Vec<(Id, UPosition)> = game
+            .map
+            .eggs()
+            .filter(|egg| egg.team_id() == 0)
+            .map(|egg| (egg.id(), egg.position()))
+            .collect();
+
+        login(&mut game, 1, "team1").await;
+
+        let eggs_after: Vec<Id> = game
+            .map
+            .eggs()
+            .filter(|egg| egg.team_id() == 0)
+            .map(|egg| egg.id())
+            .collect();
+        let consumed: Vec<&(Id, UPosition)> = eggs_before
+            .iter()
+            .filter(|(id, _)| !eggs_after.contains(id))
+            .collect();
+        assert_eq!(consumed.len(), 1);
+        assert_eq!(eggs_after.len(), eggs_before.len() - 1);
+        assert_eq!(game.clients[&1].position(), consumed[0].1);
+        assert!(!game.pending_clients.contains_key(&1));
+    }
+
+    #[tokio::test]
+    async fn test_login_spawn_is_seeded() {
+        async fn spawn(seed: u64) -> (UPosition, Direction) {
+            let mut game = Game::from_config(&test_config().seed(seed)).unwrap();
+            let _rx = add_pending(&mut game, 1);
+            login(&mut game, 1, "team1").await;
+            let player = &game.clients[&1];
+            (player.position(), player.direction())
+        }
+
+        assert_eq!(spawn(7).await, spawn(7).await);
+    }
+
+    #[tokio::test]
+    async fn test_login_notifies_guis_with_pnw_then_ebo() {
+        let mut game = Game::from_config(&test_config()).unwrap();
+        let mut gui_rx = add_gui(&mut game, 100);
+        let _rx = add_pending(&mut game, 1000);
+        let eggs_before: Vec<Id> = game.map.eggs().map(|egg| egg.id()).collect();
+
+        login(&mut game, 1000, "team1").await;
+
+        let responses = drain(&mut gui_rx);
+        assert_eq!(responses.len(), 2);
+        let ServerResponse::Gui(GUIResponse::Pnw(player_id, pos, _, _, team_name)) = &responses[0]
+        else {
+            panic!("expected pnw, got {:?}", responses[0]);
+        };
+        assert_eq!(*player_id, 1000);
+        assert_eq!(*pos, game.clients[&1000].position());
+        assert_eq!(team_name, "team1");
+        let ServerResponse::Gui(GUIResponse::Ebo(egg_id)) = &responses[1] else {
+            panic!("expected ebo, got {:?}", responses[1]);
+        };
+        assert!(eggs_before.contains(egg_id));
+        assert!(game.map.eggs().all(|egg| egg.id() != *egg_id));
+        assert_ne!(egg_id, player_id);
+    }
+
+    #[tokio::test]
+    async fn test_gui_login_snapshot_has_current_players() {
+        let mut game = Game::from_config(&test_config()).unwrap();
+        let _player_rx = add_pending(&mut game, 1);
+        login(&mut game, 1, "team1").await;
+        let player = game.clients.get_mut(&1).unwrap();
+        *player.level_mut() = ElevationLevel::Level2;
+        player.direction_mut().rotate_right();
+        let direction = player.direction();
+
+        let mut gui_rx = add_pending(&mut game, 2);
+        login(&mut game, 2, "GRAPHIC").await;
+
+        let responses = drain(&mut gui_rx);
+        assert!(matches!(
+            responses[0],
+            ServerResponse::Pending(LogAs(TeamType::Graphic))
+        ));
+        assert!(matches!(
+            responses[1],
+            ServerResponse::Gui(GUIResponse::Msz(_))
+        ));
+        let pnw = responses
+            .iter()
+            .find_map(|r| match r {
+                ServerResponse::Gui(GUIResponse::Pnw(1, _, dir, level, team)) => {
+                    Some((dir.clone(), *level, team.clone()))
+                }
+                _ => None,
+            })
+            .expect("snapshot should contain pnw for the player");
+        assert_eq!(
+            pnw,
+            (direction, ElevationLevel::Level2, "team1".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_eject_breaks_eggs_on_tile() {
+        let mut game = Game::from_config(&test_config()).unwrap();
+        let pos = UPosition::new(2, 2);
+        let (tx, mut player_rx) = mpsc::channel(128);
+        let player = Player::builder()
+            .id(1)
+            .team(0)
+            .client_tx(tx)
+            .position(pos)
+            .build()
+            .unwrap();
+        game.clients.insert(1, player);
+        let _ = game.map.break_eggs_at_pos(pos);
+        let eggs = [
+            game.map.spawn_egg(0, pos),
+            game.map.spawn_egg(0, pos),
+            game.map.spawn_egg(1, pos),
+        ];
+        let nb_eggs = game.map.eggs().count();
+        let mut gui_rx = add_gui(&mut game, 100);
+
+        game.event_scheduler.schedule(Event::Eject, 0, 1);
+        game.tick_once().await;
+
+        let broken: Vec<Id> = drain(&mut gui_rx)
+            .iter()
+            .filter_map(|r| match r {
+                ServerResponse::Gui(GUIResponse::Edi(egg_id)) => Some(*egg_id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(broken, eggs);
+        assert_eq!(game.map.eggs().count(), nb_eggs - 3);
+        assert!(drain(&mut player_rx).iter().any(|r| matches!(
+            r,
+            ServerResponse::AI(AIResponse::Shared(SharedResponse::Ok))
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_dbg_map_lists_player_positions() {
+        let mut game = Game::from_config(&test_config().debug_commands(true)).unwrap();
+        let _rx = add_player(&mut game, 1, 0);
+        *game.clients.get_mut(&1).unwrap().position_mut() = UPosition::new(3, 4);
+        let mut gui_rx = add_gui(&mut game, 100);
+
+        game.handle_gui_events((100, GUIAction::Dbg(DebugTopic::Map)))
+            .await;
+
+        let responses = drain(&mut gui_rx);
+        let [ServerResponse::Gui(GUIResponse::Dbg(lines))] = responses.as_slice() else {
+            panic!("expected a single dbg response, got {:?}", responses);
+        };
+        assert_eq!(lines, &vec!["player #1 at 3 4 facing North".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_bct_rejects_out_of_range_coordinates() {
+        let mut game = Game::from_config(&test_config()).unwrap();
+        let mut gui_rx = add_gui(&mut game, 100);
+
+        for pos in [UPosition::new(10, 0), UPosition::new(0, 10)] {
+            game.handle_gui_events((100, GUIAction::Bct(pos))).await;
+            let responses = drain(&mut gui_rx);
+            assert!(
+                matches!(
+                    responses.as_slice(),
+                    [ServerResponse::Gui(GUIResponse::Sbp)]
+                ),
+                "bct {:?} should not wrap, got {:?}",
+                pos,
+                responses
+            );
+        }
+
+        game.handle_gui_events((100, GUIAction::Bct(UPosition::new(9, 9))))
+            .await;
+        let responses = drain(&mut gui_rx);
+        assert!(matches!(
+            responses.as_slice(),
+            [ServerResponse::Gui(GUIResponse::Bct((pos, _)))] if *pos == UPosition::new(9, 9)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_resource_targets_match_densities() {
+        let mut game = Game::from_config(&test_config()).unwrap();
+        let targets = game.resource_targets();
+        game.spawn_resources();
+
+        assert_eq!(targets[Resource::Food], 50);
+        assert_eq!(targets[Resource::Linemate], 30);
+        assert_eq!(targets[Resource::Deraumere], 15);
+        assert_eq!(targets[Resource::Sibur], 10);
+        assert_eq!(targets[Resource::Mendiane], 10);
+        assert_eq!(targets[Resource::Phiras], 8);
+        assert_eq!(targets[Resource::Thystame], 5);
+        for res in Resource::iter() {
+            assert_eq!(game.map.resources()[res], targets[res]);
+        }
+    }
+
+    #[test]
+    fn test_invalid_resource_density_is_rejected() {
+        let density = ResourceDensity {
+            thystame: 2.0,
+            ..Default::default()
+        };
+        let result = Game::from_config(&test_config().resource_density(density));
+
+        assert!(matches!(
+            result,
+            Err(ServerError::InvalidDensity(Resource::Thystame, 2.0))
+        ));
+    }
+
+    #[test]
+    fn test_oversized_map_is_rejected() {
+        let result = Game::from_config(&test_config().max_map_cells(99));
+        assert!(matches!(
+            result,
+            Err(ServerError::InvalidMapSize(10, 10, 99))
+        ));
+        assert!(Game::from_config(&test_config().max_map_cells(100)).is_ok());
+
+        // The default cap takes the largest map the u8 dimensions allow
+        let mut config = test_config();
+        config.width = u8::MAX;
+        config.height = u8::MAX;
+        assert!(Game::from_config(&config).is_ok());
+
+        let mut config = test_config();
+        config.width = 0;
+        assert!(matches!(
+            Game::from_config(&config),
+            Err(ServerError::InvalidMapSize(0, 10, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_tick_once_advances_one_tick() {
+        let mut game = Game::from_config(&test_config()).unwrap();
+        let mut rx = add_player(&mut game, 1, 0);
+        game.event_scheduler.schedule(Event::Inventory, 2, 1);
+
+        game.tick_once().await;
+        assert_eq!(game.event_scheduler.current_tick(), 1);
+        assert!(drain(&mut rx).is_empty());
+
+        game.tick_once().await;
+        assert_eq!(game.event_scheduler.current_tick(), 2);
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [ServerResponse::AI(AIResponse::Inventory(_))]
+        ));
+    }
+
+    fn no_spawn_config() -> ServerConfig {
+        test_config().resource_density(ResourceDensity {
+            food: 0.0,
+            linemate: 0.0,
+            deraumere: 0.0,
+            sibur: 0.0,
+            mendiane: 0.0,
+            phiras: 0.0,
+            thystame: 0.0,
+        })
+    }
+
+    async fn run_incantation(game: &mut Game, take_before_end: bool) -> Vec<ServerResponse> {
+        let pos = UPosition::new(0, 0);
+        let _incantator_rx = add_player(game, 1, 0);
+        let _thief_rx = add_player(game, 2, 0);
+        *game.clients.get_mut(&2).unwrap().level_mut() = ElevationLevel::Level2;
+        game.map
+            .add_resource(Resource::Linemate, 1, pos, &mut game.guis);
+        let mut gui_rx = add_gui(game, 100);
+
+        game.handle_ai_events((1, AIAction::Action(Event::Incantation)))
+            .await;
+        if take_before_end {
+            game.handle_ai_events((2, AIAction::Action(Event::Take(Resource::Linemate))))
+                .await;
+        }
+        for _ in 0..=300 {
+            game.tick_once().await;
+        }
+        drain(&mut gui_rx)
+    }
+
+    #[tokio::test]
+    async fn test_pic_has_level_before_upgrade_and_pie_succeeds() {
+        let mut game = Game::from_config(&no_spawn_config()).unwrap();
+        let responses = run_incantation(&mut game, false).await;
+
+        let pic = responses.iter().find_map(|r| match r {
+            ServerResponse::Gui(GUIResponse::Pic(pos, level, players)) => {
+                Some((*pos, *level, players.clone()))
+            }
+            _ => None,
+        });
+        assert_eq!(
+            pic,
+            Some((UPosition::new(0, 0), ElevationLevel::Level1, vec![1]))
+        );
+        assert!(
+            responses
+                .iter()
+                .any(|r| matches!(r, ServerResponse::Gui(GUIResponse::Pie(_, true))))
+        );
+        assert_eq!(game.clients[&1].level(), ElevationLevel::Level2);
+    }
+
+    #[tokio::test]
+    async fn test_pie_fails_when_resources_are_taken_during_incantation() {
+        let mut game = Game::from_config(&no_spawn_config()).unwrap();
+        let responses = run_incantation(&mut game, true).await;
+
+        assert!(responses.iter().any(|r| matches!(
+            r,
+            ServerResponse::Gui(GUIResponse::Pic(_, ElevationLevel::Level1, _))
+        )));
+        assert!(responses.iter().any(|r| matches!(
+            r,
+            ServerResponse::Gui(GUIResponse::Pgt(2, Resource::Linemate))
+        )));
+        assert!(
+            responses
+                .iter()
+                .any(|r| matches!(r, ServerResponse::Gui(GUIResponse::Pie(_, false))))
+        );
+        assert_eq!(game.clients[&1].level(), ElevationLevel::Level1);
+    }
+
+    async fn incantation_end_racing_take(take_first: bool) -> (Game, Vec<ServerResponse>) {
+        let mut game = Game::from_config(&no_spawn_config()).unwrap();
+        let pos = UPosition::new(0, 0);
+        let _incantator_rx = add_player(&mut game, 1, 0);
+        let _thief_rx = add_player(&mut game, 2, 0);
+        *game.clients.get_mut(&1).unwrap().state_mut() = PlayerState::Incantating;
+        game.map
+            .add_resource(Resource::Linemate, 1, pos, &mut game.guis);
+        let mut gui_rx = add_gui(&mut game, 100);
+
+        let take = Event::Take(Resource::Linemate);
+        let end = Event::IncantationEnd(vec![1], &LEVEL_REQUIREMENTS[&ElevationLevel::Level1], pos);
+        if take_first {
+            game.event_scheduler.force_schedule(take, 1, 2);
+            game.event_scheduler.force_schedule(end, 1, 1);
+        } else {
+            game.event_scheduler.force_schedule(end, 1, 1);
+            game.event_scheduler.force_schedule(take, 1, 2);
+        }
+        game.tick_once().await;
+        let responses = drain(&mut gui_rx);
+        (game, responses)
+    }
+
+    #[tokio::test]
+    async fn test_take_before_incantation_end_in_same_tick() {
+        let (game, responses) = incantation_end_racing_take(true).await;
+
+        assert!(
+            responses
+                .iter()
+                .any(|r| matches!(r, ServerResponse::Gui(GUIResponse::Pie(_, false))))
+        );
+        assert_eq!(game.clients[&1].level(), ElevationLevel::Level1);
+        assert_eq!(game.clients[&2].inventory()[Resource::Linemate], 1);
+    }
+
+    #[tokio::test]
+    async fn test_incantation_end_before_take_in_same_tick() {
+        let (game, responses) = incantation_end_racing_take(false).await;
+
+        assert!(
+            responses
+                .iter()
+                .any(|r| matches!(r, ServerResponse::Gui(GUIResponse::Pie(_, true))))
+        );
+        assert_eq!(game.clients[&1].level(), ElevationLevel::Level2);
+        assert_eq!(game.clients[&2].inventory()[Resource::Linemate], 0);
+        assert_eq!(game.map.resources()[Resource::Linemate], 0);
+    }
+
+    #[tokio::test]
+    async fn test_unsubscribed_gui_skips_position_updates() {
+        let mut game = Game::from_config(&test_config()).unwrap();
+        let _rx = add_player(&mut game, 1, 0);
+        let mut filtered_rx = add_gui(&mut game, 100);
+        let mut full_rx = add_gui(&mut game, 101);
+        game.handle_gui_events((100, GUIAction::Subscribe(GuiCategory::Positions, false)))
+            .await;
+        drain(&mut filtered_rx);
+
+        game.event_scheduler.schedule(Event::Right, 0, 1);
+        game.tick_once().await;
+
+        let has_ppo = |responses: Vec<ServerResponse>| {
+            responses
+                .iter()
+                .any(|r| matches!(r, ServerResponse::Gui(GUIResponse::Ppo(1, ..))))
+        };
+        assert!(!has_ppo(drain(&mut filtered_rx)));
+        assert!(has_ppo(drain(&mut full_rx)));
+
+        game.handle_gui_events((100, GUIAction::Ppo(1))).await;
+        assert!(has_ppo(drain(&mut filtered_rx)));
+    }
+
+    #[tokio::test]
+    async fn test_time_command_is_opt_in() {
+        async fn ask_time(config: ServerConfig) -> Vec<ServerResponse> {
+            let mut game = Game::from_config(&config).unwrap();
+            let mut rx = add_player(&mut game, 1, 0);
+            game.tick_once().await;
+            game.tick_once().await;
+            game.handle_ai_events((1, AIAction::Action(Event::Time)))
+                .await;
+            game.tick_once().await;
+            drain(&mut rx)
+        }
+
+        assert!(matches!(
+            ask_time(test_config().time_command(true)).await.as_slice(),
+            [ServerResponse::AI(AIResponse::Time(3, 100))]
+        ));
+        assert!(matches!(
+            ask_time(test_config()).await.as_slice(),
+            [ServerResponse::AI(AIResponse::Shared(SharedResponse::Ko))]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_forward_wraps_around_and_ppo_has_new_position() {
+        let mut game = Game::from_config(&test_config()).unwrap();
+        let _rx = add_player(&mut game, 1, 0);
+        let player = game.clients.get_mut(&1).unwrap();
+        *player.position_mut() = UPosition::new(0, 4);
+        *player.direction_mut() = Direction::West;
+        let mut gui_rx = add_gui(&mut game, 100);
+
+        game.event_scheduler.schedule(Event::Forward, 0, 1);
+        game.tick_once().await;
+
+        let ppo = drain(&mut gui_rx).into_iter().find_map(|r| match r {
+            ServerResponse::Gui(GUIResponse::Ppo(1, pos, dir)) => Some((pos, dir)),
+            _ => None,
+        });
+        assert_eq!(ppo, Some((UPosition::new(9, 4), Direction::West)));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_team_login_can_be_retried() {
+        let mut game = Game::from_config(&test_config()).unwrap();
+        let mut rx = add_pending(&mut game, 1);
+
+        login(&mut game, 1, "Nope").await;
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [ServerResponse::Pending(Shared(SharedResponse::Ko))]
+        ));
+        assert!(game.pending_clients.contains_key(&1));
+        assert!(!game.clients.contains_key(&1));
+
+        login(&mut game, 1, "team2").await;
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [ServerResponse::Pending(LogAs(TeamType::IA(3, _)))]
+        ));
+        assert!(!game.pending_clients.contains_key(&1));
+        assert_eq!(game.clients[&1].team_id(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_graphic_is_reserved_for_guis() {
+        let config = ServerConfig::new(
+            "127.0.0.1".to_string(),
+            0,
+            10,
+            10,
+            vec!["team1".to_string(), "GRAPHIC".to_string()],
+            4,
+            100,
+        );
+        let mut game = Game::from_config(&config).unwrap();
+        assert_eq!(game.team_names(), vec!["team1".to_string()]);
+
+        let mut rx = add_pending(&mut game, 1);
+        login(&mut game, 1, "GRAPHIC").await;
+
+        assert!(matches!(
+            drain(&mut rx).first(),
+            Some(ServerResponse::Pending(LogAs(TeamType::Graphic)))
+        ));
+        assert!(game.guis.contains_key(&1));
+        assert!(!game.clients.contains_key(&1));
+    }
+
+    #[tokio::test]
+    async fn test_slow_gui_does_not_stall_tick() {
+        let mut game = Game::from_config(&test_config()).unwrap();
+        let _rx = add_player(&mut game, 1, 0);
+        let mut gui_rxs: Vec<_> = (100..150).map(|id| add_gui(&mut game, id)).collect();
+        let (client_tx, mut slow_rx) = mpsc::channel(1);
+        let slow_gui = GuiBuilder::new()
+            .pending_client(PendingClient {
+                client_id: 200,
+                client_tx,
+            })
+            .build()
+            .unwrap();
+        game.guis.insert(200, slow_gui);
+
+        for _ in 0..10 {
+            game.event_scheduler.schedule(Event::Right, 0, 1);
+            game.tick_once().await;
+        }
+
+        assert_eq!(game.current_tick(), 10);
+        for rx in &mut gui_rxs {
+            let ppo_count = drain(rx)
+                .iter()
+                .filter(|r| matches!(r, ServerResponse::Gui(GUIResponse::Ppo(1, ..))))
+                .count();
+            assert_eq!(ppo_count, 10);
+        }
+        assert_eq!(drain(&mut slow_rx).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_disconnected_player_cannot_resume() {
+        let mut game = Game::from_config(&test_config()).unwrap();
+        let _rx = add_pending(&mut game, 1);
+        login(&mut game, 1, "team1").await;
+        game.clients
+            .get_mut(&1)
+            .unwrap()
+            .add_resource(Resource::Sibur, 1);
+        let eggs_left = game.map.nb_eggs_by_team(0);
+
+        game.handle_ai_events((1, AIAction::Shared(SharedAction::Disconnected)))
+            .await;
+        assert!(!game.clients.contains_key(&1));
+        assert_eq!(game.map.nb_eggs_by_team(0), eggs_left);
+
+        let _rx = add_pending(&mut game, 2);
+        login(&mut game, 2, "team1").await;
+        assert_eq!(game.map.nb_eggs_by_team(0), eggs_left - 1);
+        assert_eq!(game.clients[&2].inventory()[Resource::Sibur], 0);
+        assert_eq!(game.clients[&2].level(), ElevationLevel::Level1);
+    }
+
+    #[tokio::test]
+    async fn test_contested_take_goes_to_first_scheduler() {
+        let mut game = Game::from_config(&no_spawn_config()).unwrap();
+        let pos = UPosition::new(0, 0);
+        let mut first_rx = add_player(&mut game, 1, 0);
+        let mut second_rx = add_player(&mut game, 2, 1);
+        game.map
+            .add_resource(Resource::Food, 1, pos, &mut game.guis);
+        let mut gui_rx = add_gui(&mut game, 100);
+
+        game.handle_ai_events((1, AIAction::Action(Event::Take(Resource::Food))))
+            .await;
+        game.handle_ai_events((2, AIAction::Action(Event::Take(Resource::Food))))
+            .await;
+        for _ in 0..7 {
+            game.tick_once().await;
+        }
+
+        assert!(drain(&mut first_rx).iter().any(|r| matches!(
+            r,
+            ServerResponse::AI(AIResponse::Shared(SharedResponse::Ok))
+        )));
+        assert!(drain(&mut second_rx).iter().any(|r| matches!(
+            r,
+            ServerResponse::AI(AIResponse::Shared(SharedResponse::Ko))
+        )));
+        let responses = drain(&mut gui_rx);
+        let takes: Vec<Id> = responses
+            .iter()
+            .filter_map(|r| match r {
+                ServerResponse::Gui(GUIResponse::Pgt(id, Resource::Food)) => Some(*id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(takes, [1]);
+        let bct: Vec<u64> = responses
+            .iter()
+            .filter_map(|r| match r {
+                ServerResponse::Gui(GUIResponse::Bct((p, resources))) if *p == pos => {
+                    Some(resources[Resource::Food])
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(bct, [0]);
+        assert_eq!(game.map.resources()[Resource::Food], 0);
+    }
+
+    #[tokio::test]
+    async fn test_set_accumulates_and_is_visible_to_look_and_bct() {
+        let mut game = Game::from_config(&no_spawn_config()).unwrap();
+        let pos = UPosition::new(0, 0);
+        let mut rx = add_player(&mut game, 1, 0);
+        game.clients
+            .get_mut(&1)
+            .unwrap()
+            .add_resource(Resource::Linemate, 1);
+        game.map
+            .add_resource(Resource::Linemate, 1, pos, &mut game.guis);
+        let mut gui_rx = add_gui(&mut game, 100);
+
+        game.handle_ai_events((1, AIAction::Action(Event::Set(Resource::Linemate))))
+            .await;
+        game.handle_ai_events((1, AIAction::Action(Event::Look)))
+            .await;
+        for _ in 0..14 {
+            game.tick_once().await;
+        }
+
+        let look = drain(&mut rx).into_iter().find_map(|r| match r {
+            ServerResponse::AI(AIResponse::Look(look)) => Some(look),
+            _ => None,
+        });
+        let look = look.expect("look should be answered");
+        assert_eq!(look[0].2[Resource::Linemate], 2);
+        assert!(
+            LookFormat(&look)
+                .to_string()
+                .starts_with("[player linemate linemate,")
+        );
+        assert!(drain(&mut gui_rx).iter().any(|r| matches!(
+            r,
+            ServerResponse::Gui(GUIResponse::Bct((p, resources)))
+                if *p == pos && resources[Resource::Linemate] == 2
+        )));
+        assert_eq!(game.map.resources()[Resource::Linemate], 2);
+    }
+
+    #[tokio::test]
+    async fn test_team_membership_and_level_counts() {
+        let mut game = Game::from_config(&test_config()).unwrap();
+        let _rx1 = add_player(&mut game, 1, 0);
+        let _rx2 = add_player(&mut game, 2, 0);
+        let _rx3 = add_player(&mut game, 3, 1);
+        *game.clients.get_mut(&2).unwrap().level_mut() = ElevationLevel::Level3;
+
+        let mut members: Vec<Id> = game.players_of_team(0).map(|p| p.id()).collect();
+        members.sort();
+        assert_eq!(members, [1, 2]);
+        assert_eq!(game.players_of_team(1).count(), 1);
+
+        let counts = game.count_by_level_for_team(0);
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts[&ElevationLevel::Level1], 1);
+        assert_eq!(counts[&ElevationLevel::Level3], 1);
+        assert!(game.count_by_level_for_team(5).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fork_notifies_enw_with_new_egg() {
+        let mut game = Game::from_config(&test_config()).unwrap();
+        let mut rx = add_player(&mut game, 1, 0);
+        let size = game.map.size();
+        game.clients.get_mut(&1).unwrap().move_player(3, 4, &size);
+        let last_egg = game.map.eggs().map(|egg| egg.id()).max().unwrap();
+        let mut gui_rx = add_gui(&mut game, 100);
+
+        game.handle_ai_events((1, AIAction::Action(Event::Fork)))
+            .await;
+        for _ in 0..42 {
+            game.tick_once().await;
+        }
+
+        let forks: Vec<GUIResponse> = drain(&mut gui_rx)
+            .into_iter()
+            .filter_map(|r| match r {
+                ServerResponse::Gui(response @ (GUIResponse::Pfk(_) | GUIResponse::Enw(..))) => {
+                    Some(response)
+                }
+                _ => None,
+            })
+            .collect();
+        let [GUIResponse::Pfk(1), GUIResponse::Enw(egg_id, Some(1), pos)] = forks[..] else {
+            panic!("expected pfk then enw, got {:?}", forks);
+        };
+        assert!(egg_id > last_egg);
+        assert_eq!(pos, UPosition::new(3, 4));
+        assert!(game.map.eggs_at_pos(pos).any(|egg| egg.id() == egg_id));
+
+        game.handle_ai_events((1, AIAction::Action(Event::ConnectNbr)))
+            .await;
+        game.tick_once().await;
+        assert!(
+            drain(&mut rx)
+                .iter()
+                .any(|r| matches!(r, ServerResponse::AI(AIResponse::ConnectNbr(5))))
+        );
+    }
+
+    async fn fork_egg(game: &mut Game) -> Id {
+        game.handle_ai_events((1, AIAction::Action(Event::Fork)))
+            .await;
+        for _ in 0..42 {
+            game.tick_once().await;
+        }
+        game.map.eggs().map(|egg| egg.id()).max().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_unclaimed_egg_expires() {
+        let mut config = no_spawn_config().egg_lifetime(Some(10));
+        config.clients_nb = 0;
+        let mut game = Game::from_config(&config).unwrap();
+        let _rx = add_player(&mut game, 1, 0);
+        let egg_id = fork_egg(&mut game).await;
+        let mut gui_rx = add_gui(&mut game, 100);
+
+        for _ in 0..9 {
+            game.tick_once().await;
+        }
+        assert_eq!(game.map.nb_eggs_by_team(0), 1);
+        game.tick_once().await;
+
+        assert_eq!(game.map.nb_eggs_by_team(0), 0);
+        assert!(
+            drain(&mut gui_rx)
+                .iter()
+                .any(|r| matches!(r, ServerResponse::Gui(GUIResponse::Edi(id)) if *id == egg_id))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_claimed_egg_does_not_expire() {
+        let mut config = no_spawn_config().egg_lifetime(Some(10));
+        config.clients_nb = 0;
+        let mut game = Game::from_config(&config).unwrap();
+        let _rx = add_player(&mut game, 1, 0);
+        fork_egg(&mut game).await;
+        let _pending_rx = add_pending(&mut game, 2);
+        login(&mut game, 2, "team1").await;
+        assert_eq!(game.players_of_team(0).count(), 2);
+        let mut gui_rx = add_gui(&mut game, 100);
+
+        for _ in 0..10 {
+            game.tick_once().await;
+        }
+
+        assert!(
+            !drain(&mut gui_rx)
+                .iter()
+                .any(|r| matches!(r, ServerResponse::Gui(GUIResponse::Edi(_))))
+        );
+        assert_eq!(game.event_scheduler.pending_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_fork_cost_is_configurable() {
+        let mut game = Game::from_config(&test_config().fork_cost(5)).unwrap();
+        let mut rx = add_player(&mut game, 1, 0);
+
+        game.handle_ai_events((1, AIAction::Action(Event::Fork)))
+            .await;
+        for _ in 0..5 {
+            game.tick_once().await;
+        }
+
+        assert!(drain(&mut rx).iter().any(|r| matches!(
+            r,
+            ServerResponse::AI(AIResponse::Shared(SharedResponse::Ok))
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_roster_command_lists_live_players() {
+        let mut game = Game::from_config(&test_config().roster_command(true)).unwrap();
+        let _rx1 = add_player(&mut game, 2, 1);
+        let _rx2 = add_player(&mut game, 1, 0);
+        let mut gui_rx = add_gui(&mut game, 100);
+
+        game.handle_gui_events((100, GUIAction::Pls)).await;
+
+        let roster: Vec<(Id, String)> = drain(&mut gui_rx)
+            .into_iter()
+            .map(|r| match r {
+                ServerResponse::Gui(GUIResponse::Pnw(id, .., team)) => (id, team),
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+        assert_eq!(roster, [(1, "team1".to_string()), (2, "team2".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_roster_command_is_opt_in() {
+        let mut game = Game::from_config(&test_config()).unwrap();
+        let _rx = add_player(&mut game, 1, 0);
+        let mut gui_rx = add_gui(&mut game, 100);
+
+        game.handle_gui_events((100, GUIAction::Pls)).await;
+
+        assert!(matches!(
+            drain(&mut gui_rx).as_slice(),
+            [ServerResponse::Gui(GUIResponse::Shared(SharedResponse::Ko))]
+        ));
+    }
+
+    fn win_with_team1(game: &mut Game) -> Vec<mpsc::Receiver<ServerResponse>> {
+        (1..=WINNING_PLAYERS)
+            .map(|id| {
+                let rx = add_player(game, id, 0);
+                *game.clients.get_mut(&id).unwrap().level_mut() = ElevationLevel::Level8;
+                rx
+            })
+            .collect()
+    }
+
+    fn got_dead(rx: &mut mpsc::Receiver<ServerResponse>) -> bool {
+        drain(rx)
+            .iter()
+            .any(|r| matches!(r, ServerResponse::AI(AIResponse::Dead)))
+    }
+
+    #[tokio::test]
+    async fn test_win_freezes_the_game() {
+        let mut game = Game::from_config(&test_config()).unwrap();
+        let mut players_rx = win_with_team1(&mut game);
+        let mut gui_rx = add_gui(&mut game, 100);
+
+        game.tick_once().await;
+        assert_eq!(game.winner(), Some(0));
+        assert!(!game.is_stopped());
+        assert!(
+            drain(&mut gui_rx).iter().any(
+                |r| matches!(r, ServerResponse::Gui(GUIResponse::Seg(team)) if team == "team1")
+            )
+        );
+
+        let tick = game.current_tick();
+        for _ in 0..10 {
+            game.tick_once().await;
+        }
+        assert_eq!(game.current_tick(), tick);
+        assert_eq!(game.players().count(), 6);
+        assert!(!got_dead(&mut players_rx[0]));
+    }
+
+    #[tokio::test]
+    async fn test_win_with_stop_kills_players() {
+        let mut game = Game::from_config(&test_config().end_action(EndAction::Stop)).unwrap();
+        let mut players_rx = win_with_team1(&mut game);
+        let mut gui_rx = add_gui(&mut game, 100);
+
+        game.tick_once().await;
+
+        assert!(game.is_stopped());
+        assert!(players_rx.iter_mut().all(got_dead));
+        let responses = drain(&mut gui_rx);
+        assert!(
+            responses
+                .iter()
+                .any(|r| matches!(r, ServerResponse::Gui(GUIResponse::Seg(_))))
+        );
+        assert_eq!(
+            responses
+                .iter()
+                .filter(|r| matches!(r, ServerResponse::Gui(GUIResponse::Pdi(_))))
+                .count(),
+            6
+        );
+    }
+
+    #[tokio::test]
+    async fn test_win_with_restart_starts_a_fresh_game() {
+        let mut game = Game::from_config(&test_config().end_action(EndAction::Restart(3))).unwrap();
+        let mut players_rx = win_with_team1(&mut game);
+        let mut gui_rx = add_gui(&mut game, 100);
+
+        game.tick_once().await;
+        assert_eq!(game.winner(), Some(0));
+        game.tick_once().await;
+        game.tick_once().await;
+        assert!(!got_dead(&mut players_rx[0]));
+        drain(&mut gui_rx);
+
+        game.tick_once().await;
+
+        assert_eq!(game.winner(), None);
+        assert_eq!(game.players().count(), 0);
+        assert_eq!(game.current_tick(), 0);
+        assert_eq!(game.map.nb_eggs_by_team(0), 4);
+        assert!(got_dead(&mut players_rx[0]));
+        let responses = drain(&mut gui_rx);
+        let msz = responses
+            .iter()
+            .position(|r| matches!(r, ServerResponse::Gui(GUIResponse::Msz(_))))
+            .expect("guis get a new snapshot");
+        assert!(
+            responses[..msz]
+                .iter()
+                .all(|r| matches!(r, ServerResponse::Gui(GUIResponse::Pdi(_))))
+        );
+
+        let _rx = add_pending(&mut game, 50);
+        login(&mut game, 50, "team1").await;
+        assert_eq!(game.players().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sst_is_clamped() {
+        let mut game = Game::from_config(&test_config().max_freq(1000)).unwrap();
+        let mut gui_rx = add_gui(&mut game, 100);
+
+        for (requested, applied) in [(0, 1), (10_000_000, 1000), (50, 50)] {
+            game.handle_gui_events((100, GUIAction::Sst(requested)))
+                .await;
+            assert_eq!(game.frequency(), applied);
+            assert!(
+                drain(&mut gui_rx).iter().any(
+                    |r| matches!(r, ServerResponse::Gui(GUIResponse::Sst(f)) if *f == applied)
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_initial_frequency_is_clamped() {
+        let mut config = test_config().max_freq(50);
+        assert_eq!(Game::from_config(&config).unwrap().frequency(), 50);
+        config.freq = 0;
+        assert_eq!(Game::from_config(&config).unwrap().frequency(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sgt_reports_exact_sst_frequency() {
+        let mut game = Game::from_config(&test_config()).unwrap();
+        let mut gui_rx = add_gui(&mut game, 100);
+
+        game.handle_gui_events((100, GUIAction::Sst(7))).await;
+        drain(&mut gui_rx);
+        game.handle_gui_events((100, GUIAction::Sgt)).await;
+
+        assert!(matches!(
+            drain(&mut gui_rx).as_slice(),
+            [ServerResponse::Gui(GUIResponse::Sgt(7))]
+        ));
+    }
+
+    #[test]
+    fn test_mct_is_row_major() {
+        let mut config = no_spawn_config();
+        config.width = 3;
+        config.height = 2;
+        let game = Game::from_config(&config).unwrap();
+
+        let positions: Vec<(u64, u64)> = game
+            .mct()
+            .iter()
+            .map(|(pos, _)| (pos.x(), pos.y()))
+            .collect();
+        assert_eq!(positions, [(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (2, 1)]);
+    }
+
+    fn place(game: &mut Game, id: Id, pos: UPosition, direction: Direction) {
+        let player = game.clients.get_mut(&id).unwrap();
+        *player.position_mut() = pos;
+        *player.direction_mut() = direction;
+    }
+
+    fn heard(rx: &mut mpsc::Receiver<ServerResponse>) -> Vec<(u8, String)> {
+        drain(rx)
+            .into_iter()
+            .filter_map(|r| match r {
+                ServerResponse::AI(AIResponse::Broadcast(dir, text)) => {
+                    Some((dir, text.to_string()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_reaches_everyone_but_the_emitter() {
+        let mut game = Game::from_config(&test_config()).unwrap();
+        let mut emitter_rx = add_player(&mut game, 1, 0);
+        let mut front_rx = add_player(&mut game, 2, 0);
+        let mut behind_rx = add_player(&mut game, 3, 1);
+        place(&mut game, 1, UPosition::new(5, 5), Direction::North);
+        place(&mut game, 2, UPosition::new(2, 5), Direction::East);
+        place(&mut game, 3, UPosition::new(8, 5), Direction::East);
+        let mut gui_rxs = [add_gui(&mut game, 100), add_gui(&mut game, 101)];
+
+        game.event_scheduler
+            .schedule(Event::Broadcast("hello world".into()), 0, 1);
+        game.tick_once().await;
+
+        let emitter = drain(&mut emitter_rx);
+        assert!(matches!(
+            emitter.as_slice(),
+            [ServerResponse::AI(AIResponse::Shared(SharedResponse::Ok))]
+        ));
+        assert_eq!(heard(&mut front_rx), [(1, "hello world".to_string())]);
+        assert_eq!(heard(&mut behind_rx), [(5, "hello world".to_string())]);
+        for gui_rx in &mut gui_rxs {
+            let pbc: Vec<(Id, String)> = drain(gui_rx)
+                .into_iter()
+                .filter_map(|r| match r {
+                    ServerResponse::Gui(GUIResponse::Pbc(id, text)) => Some((id, text.to_string())),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(pbc, [(1, "hello world".to_string())]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_on_the_emitter_tile_comes_from_everywhere() {
+        let mut game = Game::from_config(&test_config()).unwrap();
+        let mut emitter_rx = add_player(&mut game, 1, 0);
+        let mut neighbour_rx = add_player(&mut game, 2, 1);
+        place(&mut game, 1, UPosition::new(4, 4), Direction::North);
+        place(&mut game, 2, UPosition::new(4, 4), Direction::West);
+
+        game.event_scheduler
+            .schedule(Event::Broadcast("here".into()), 0, 1);
+        game.tick_once().await;
+
+        assert_eq!(heard(&mut neighbour_rx), [(0, "here".to_string())]);
+        assert!(heard(&mut emitter_rx).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_look_lists_eggs_only_when_enabled() {
+        async fn look_with(config: ServerConfig) -> String {
+            let mut game = Game::from_config(&config).unwrap();
+            let mut rx = add_player(&mut game, 1, 0);
+            place(&mut game, 1, UPosition::new(3, 3), Direction::North);
+            game.map.spawn_egg(0, UPosition::new(3, 3));
+            game.map.spawn_egg(1, UPosition::new(3, 3));
+
+            game.event_scheduler.schedule(Event::Look, 0, 1);
+            game.tick_once().await;
+            drain(&mut rx)
+                .into_iter()
+                .find_map(|r| match r {
+                    ServerResponse::AI(AIResponse::Look(look)) => {
+                        Some(LookFormat(&look).to_string())
+                    }
+                    _ => None,
+                })
+                .expect("look should be answered")
+        }
+
+        let mut config = no_spawn_config();
+        config.clients_nb = 0;
+        assert!(look_with(config.clone()).await.starts_with("[player,"));
+        assert!(
+            look_with(config.look_eggs(true))
+                .await
+                .starts_with("[player egg egg,")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_kill_command_removes_the_player() {
+        let mut game = Game::from_config(&test_config().admin_commands(true)).unwrap();
+        let mut rx = add_player(&mut game, 1, 0);
+        let _other_rx = add_player(&mut game, 2, 0);
+        let mut gui_rx = add_gui(&mut game, 100);
+        game.event_scheduler.schedule(Event::Look, 7, 1);
+
+        game.handle_gui_events((100, GUIAction::Kill(1))).await;
+
+        assert!(got_dead(&mut rx));
+        assert!(!game.clients.contains_key(&1));
+        assert!(game.event_scheduler.events_for_player(1).is_empty());
+        assert!(matches!(
+            drain(&mut gui_rx).as_slice(),
+            [ServerResponse::Gui(GUIResponse::Pdi(1))]
+        ));
+
+        game.handle_gui_events((100, GUIAction::Kill(1))).await;
+        assert!(matches!(
+            drain(&mut gui_rx).as_slice(),
+            [ServerResponse::Gui(GUIResponse::Sbp)]
+        ));
+        for _ in 0..8 {
+            game.tick_once().await;
+        }
+        assert!(drain(&mut rx).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_kill_command_is_opt_in() {
+        let mut game = Game::from_config(&test_config()).unwrap();
+        let mut rx = add_player(&mut game, 1, 0);
+        let mut gui_rx = add_gui(&mut game, 100);
+
+        game.handle_gui_events((100, GUIAction::Kill(1))).await;
+
+        assert!(matches!(
+            drain(&mut gui_rx).as_slice(),
+            [ServerResponse::Gui(GUIResponse::Shared(SharedResponse::Ko))]
+        ));
+        assert!(!got_dead(&mut rx));
+        assert!(game.clients.contains_key(&1));
+    }
+
+    #[tokio::test]
+    async fn test_setr_and_tp_commands() {
+        let mut game = Game::from_config(&no_spawn_config().admin_commands(true)).unwrap();
+        let _rx = add_player(&mut game, 1, 0);
+        let mut gui_rx = add_gui(&mut game, 100);
+        let pos = UPosition::new(2, 3);
+
+        game.handle_gui_events((
+            100,
+            GUIAction::Setr(pos, vec![(Resource::Linemate, 2), (Resource::Sibur, 1)]),
+        ))
+        .await;
+        game.handle_gui_events((100, GUIAction::Setr(pos, vec![(Resource::Linemate, 1)])))
+            .await;
+        game.handle_gui_events((100, GUIAction::Tp(1, pos))).await;
+
+        let responses = drain(&mut gui_rx);
+        let [
+            ServerResponse::Gui(GUIResponse::Bct(_)),
+            ServerResponse::Gui(GUIResponse::Bct((bct_pos, resources))),
+            ServerResponse::Gui(GUIResponse::Ppo(1, ppo_pos, _)),
+        ] = responses.as_slice()
+        else {
+            panic!("unexpected {:?}", responses);
+        };
+        assert_eq!((*bct_pos, *ppo_pos), (pos, pos));
+        assert_eq!(
+            resources,
+            &Resources::builder().linemate(1).sibur(1).build()
+        );
+        assert_eq!(game.map.resources()[Resource::Linemate], 1);
+        assert_eq!(game.clients[&1].position(), pos);
+
+        let outside = UPosition::new(10, 0);
+        game.handle_gui_events((100, GUIAction::Setr(outside, vec![(Resource::Food, 1)])))
+            .await;
+        game.handle_gui_events((100, GUIAction::Tp(1, outside)))
+            .await;
+        game.handle_gui_events((100, GUIAction::Tp(7, pos))).await;
+        assert!(matches!(
+            drain(&mut gui_rx).as_slice(),
+            [
+                ServerResponse::Gui(GUIResponse::Sbp),
+                ServerResponse::Gui(GUIResponse::Sbp),
+                ServerResponse::Gui(GUIResponse::Sbp)
+            ]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_setr_and_tp_are_opt_in() {
+        let mut game = Game::from_config(&test_config()).unwrap();
+        let _rx = add_player(&mut game, 1, 0);
+        let mut gui_rx = add_gui(&mut game, 100);
+        let before = game.clients[&1].position();
+
+        game.handle_gui_events((100, GUIAction::Tp(1, UPosition::new(9, 9))))
+            .await;
+        game.handle_gui_events((
+            100,
+            GUIAction::Setr(UPosition::new(0, 0), vec![(Resource::Food, 1)]),
+        ))
+        .await;
+
+        assert!(matches!(
+            drain(&mut gui_rx).as_slice(),
+            [
+                ServerResponse::Gui(GUIResponse::Shared(SharedResponse::Ko)),
+                ServerResponse::Gui(GUIResponse::Shared(SharedResponse::Ko))
+            ]
+        ));
+        assert_eq!(game.clients[&1].position(), before);
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_commands_start_after_the_previous_one() {
+        let mut game = Game::from_config(&test_config()).unwrap();
+        let mut rx = add_player(&mut game, 1, 0);
+        for event in [
+            Event::Look,
+            Event::Inventory,
+            Event::Forward,
+            Event::ConnectNbr,
+        ] {
+            game.handle_ai_events((1, AIAction::Action(event))).await;
+        }
+
+        let mut answered_at = vec![];
+        for tick in 1..=20 {
+            game.tick_once().await;
+            for response in drain(&mut rx) {
+                let ServerResponse::AI(response) = response else {
+                    continue;
+                };
+                let kind = match response {
+                    AIResponse::Look(_) => "look",
+                    AIResponse::Inventory(_) => "inventory",
+                    AIResponse::Shared(SharedResponse::Ok) => "forward",
+                    AIResponse::ConnectNbr(_) => "connect_nbr",
+                    other => panic!("unexpected {:?}", other),
+                };
+                answered_at.push((kind, tick));
+            }
+        }
+        assert_eq!(
+            answered_at,
+            [
+                ("look", 7),
+                ("inventory", 8),
+                ("forward", 15),
+                ("connect_nbr", 15)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_eating_sends_pin_right_away() {
+        let mut game = Game::from_config(&test_config()).unwrap();
+        let _rx = add_player(&mut game, 1, 0);
+        let mut gui_rx = add_gui(&mut game, 100);
+        let pins = |rx: &mut mpsc::Receiver<ServerResponse>| {
+            drain(rx)
+                .into_iter()
+                .filter_map(|r| match r {
+                    ServerResponse::Gui(GUIResponse::Pin(id, _, inventory)) => {
+                        Some((id, inventory[Resource::Food]))
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for _ in 1..REFILL_PER_FOOD {
+            game.tick_once().await;
+        }
+        assert!(pins(&mut gui_rx).is_empty());
+
+        game.tick_once().await;
+        assert_eq!(pins(&mut gui_rx), [(1, 9)]);
+        assert_eq!(game.clients[&1].inventory()[Resource::Food], 9);
+    }
+
+    #[tokio::test]
+    async fn test_egg_list_command() {
+        let mut config = test_config().roster_command(true);
+        config.clients_nb = 0;
+        let mut game = Game::from_config(&config).unwrap();
+        let start_egg = game.map.spawn_egg(1, UPosition::new(2, 2));
+        let forked_egg = game.map.lay_egg(0, UPosition::new(4, 1), 7);
+        let mut gui_rx = add_gui(&mut game, 100);
+
+        game.handle_gui_events((100, GUIAction::Egl)).await;
+
+        let eggs: Vec<(Id, Option<Id>, UPosition)> = drain(&mut gui_rx)
+            .into_iter()
+            .map(|r| match r {
+                ServerResponse::Gui(GUIResponse::Enw(egg_id, layer, pos)) => (egg_id, layer, pos),
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            eggs,
+            [
+                (start_egg, None, UPosition::new(2, 2)),
+                (forked_egg, Some(7), UPosition::new(4, 1))
+            ]
+        );
+
+        let mut strict = Game::from_config(&test_config()).unwrap();
+        let mut strict_rx = add_gui(&mut strict, 100);
+        strict.handle_gui_events((100, GUIAction::Egl)).await;
+        assert!(matches!(
+            drain(&mut strict_rx).as_slice(),
+            [ServerResponse::Gui(GUIResponse::Shared(SharedResponse::Ko))]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_participant_look_waits_for_the_incantation() {
+        let mut game = Game::from_config(&no_spawn_config()).unwrap();
+        let pos = UPosition::new(0, 0);
+        let _emitter_rx = add_player(&mut game, 1, 0);
+        let mut participant_rx = add_player(&mut game, 2, 0);
+        game.map
+            .add_resource(Resource::Linemate, 1, pos, &mut game.guis);
+
+        game.handle_ai_events((1, AIAction::Action(Event::Incantation)))
+            .await;
+        game.handle_ai_events((2, AIAction::Action(Event::Look)))
+            .await;
+
+        let mut answered_at = vec![];
+        for tick in 1..=310 {
+            game.tick_once().await;
+            for response in drain(&mut participant_rx) {
+                match response {
+                    ServerResponse::AI(AIResponse::Incantating) => {
+                        answered_at.push(("start", tick))
+                    }
+                    ServerResponse::AI(AIResponse::LevelUp(_)) => answered_at.push(("end", tick)),
+                    ServerResponse::AI(AIResponse::Look(_)) => answered_at.push(("look", tick)),
+                    other => panic!("unexpected {:?}", other),
+                }
+            }
+        }
+        assert_eq!(answered_at, [("start", 1), ("end", 301), ("look", 307)]);
+    }
+
+    #[tokio::test]
+    async fn test_ai_bad_parameters_are_answered_ko() {
+        let mut game = Game::from_config(&test_config()).unwrap();
+        let mut rx = add_player(&mut game, 1, 0);
+        let mut handler = AiHandler::new(1);
+
+        for command in ["Take gold", "Broadcast"] {
+            let EventType::AI(event) = handler.parse_command(command.to_string()) else {
+                panic!("{} is not an AI event", command);
+            };
+            game.handle_ai_events((event.id, event.action)).await;
+        }
+        game.tick_once().await;
+
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [
+                ServerResponse::AI(AIResponse::Shared(SharedResponse::Ko)),
+                ServerResponse::AI(AIResponse::Shared(SharedResponse::Ko))
+            ]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_killed_emitter_incantation_still_ends() {
+        let mut game = Game::from_config(&no_spawn_config().admin_commands(true)).unwrap();
+        let pos = UPosition::new(0, 0);
+        let _emitter_rx = add_player(&mut game, 1, 0);
+        let mut participant_rx = add_player(&mut game, 2, 0);
+        game.map
+            .add_resource(Resource::Linemate, 1, pos, &mut game.guis);
+        let _gui_rx = add_gui(&mut game, 100);
+
+        game.handle_ai_events((1, AIAction::Action(Event::Incantation)))
+            .await;
+        game.tick_once().await;
+        assert!(game.clients[&2].is_incantating());
+        game.handle_gui_events((100, GUIAction::Kill(1))).await;
+        assert!(game.event_scheduler.events_for_player(1).is_empty());
+
+        for _ in 0..300 {
+            game.tick_once().await;
+        }
+        assert!(!game.clients[&2].is_incantating());
+        assert!(drain(&mut participant_rx).iter().any(|r| matches!(
+            r,
+            ServerResponse::AI(AIResponse::LevelUp(ElevationLevel::Level2))
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_ko_replies_do_not_use_queue_slots() {
+        let mut game = Game::from_config(&no_spawn_config()).unwrap();
+        let _rx = add_player(&mut game, 1, 0);
+        for _ in 0..9 {
+            game.handle_ai_events((1, AIAction::Action(Event::Forward)))
+                .await;
+        }
+        game.handle_ai_events((1, AIAction::Shared(SharedAction::InvalidAction)))
+            .await;
+        game.handle_ai_events((1, AIAction::Action(Event::Forward)))
+            .await;
+        assert_eq!(game.event_scheduler.events_for_player(1).len(), 11);
+
+        // Even with the queue full, a bad command is still answered
+        game.handle_ai_events((1, AIAction::Shared(SharedAction::InvalidParameters)))
+            .await;
+        let events = game.event_scheduler.events_for_player(1);
+        assert_eq!(events.len(), 12);
+        assert!(matches!(events[11].data, Event::Ko));
+    }
+
+    #[tokio::test]
+    async fn test_pin_after_take_includes_the_taken_resource() {
+        let mut game = Game::from_config(&no_spawn_config()).unwrap();
+        let _rx = add_player(&mut game, 1, 0);
+        let pos = game.clients[&1].position();
+        game.map
+            .add_resource(Resource::Phiras, 1, pos, &mut game.guis);
+        let mut gui_rx = add_gui(&mut game, 100);
+        game.event_scheduler
+            .schedule(Event::Take(Resource::Phiras), 0, 1);
+
+        game.tick_once().await;
+
+        let responses = drain(&mut gui_rx);
+        let pgt = responses
+            .iter()
+            .position(|r| {
+                matches!(
+                    r,
+                    ServerResponse::Gui(GUIResponse::Pgt(1, Resource::Phiras))
+                )
+            })
+            .expect("guis see the take");
+        assert!(matches!(
+            &responses[pgt + 1],
+            ServerResponse::Gui(GUIResponse::Pin(1, _, inventory)) if inventory[Resource::Phiras] == 1
+        ));
+    }
+}
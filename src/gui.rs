@@ -1,11 +1,40 @@
 use crate::pending::PendingClient;
-use crate::protocol::{ClientSender, HasId, Id, ServerResponse};
+use crate::protocol::{ClientSender, GUIResponse, GuiCategory, HasId, Id, ServerResponse};
 use tokio::sync::mpsc::Sender;
 
 #[derive(Debug)]
 pub struct Gui {
     id: Id,
     gui_tx: Sender<ServerResponse>,
+    subscriptions: u8,
+}
+
+const ALL_CATEGORIES: u8 = u8::MAX;
+
+impl Gui {
+    pub fn is_subscribed(&self, category: GuiCategory) -> bool {
+        self.subscriptions & (1 << category as u8) != 0
+    }
+
+    pub fn set_subscribed(&mut self, category: GuiCategory, subscribed: bool) {
+        if subscribed {
+            self.subscriptions |= 1 << category as u8;
+        } else {
+            self.subscriptions &= !(1 << category as u8);
+        }
+    }
+
+    /// Sends an unsolicited update, unless the gui unsubscribed from its category.
+    ///
+    /// Fanning out costs one move per gui on the game loop: formatting and writing
+    /// happen in each connection task, and a gui whose channel is full loses the
+    /// update instead of blocking the tick.
+    pub fn notify(&self, response: GUIResponse) -> &Self {
+        match response.category() {
+            Some(category) if !self.is_subscribed(category) => self,
+            _ => self.send_to_client(ServerResponse::Gui(response)),
+        }
+    }
 }
 
 impl HasId for Gui {
@@ -50,6 +79,45 @@ impl GuiBuilder {
         let gui_tx = self.gui_tx.ok_or("GUI channel is required")?;
         let id = self.id.ok_or("GUI ID is required")?;
 
-        Ok(Gui { id, gui_tx })
+        Ok(Gui {
+            id,
+            gui_tx,
+            subscriptions: ALL_CATEGORIES,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec2::UPosition;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_notify_skips_unsubscribed_categories() {
+        let (gui_tx, mut rx) = mpsc::channel(8);
+        let mut gui = GuiBuilder::new()
+            .pending_client(PendingClient {
+                client_id: 0,
+                client_tx: gui_tx,
+            })
+            .build()
+            .unwrap();
+        gui.set_subscribed(GuiCategory::Resources, false);
+
+        gui.notify(GUIResponse::Bct((UPosition::new(0, 0), Default::default())));
+        gui.notify(GUIResponse::Pdi(3));
+        gui.set_subscribed(GuiCategory::Resources, true);
+        gui.notify(GUIResponse::Mct(vec![]));
+
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(ServerResponse::Gui(GUIResponse::Pdi(3)))
+        ));
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(ServerResponse::Gui(GUIResponse::Mct(_)))
+        ));
+        assert!(rx.try_recv().is_err());
     }
 }
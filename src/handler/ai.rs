@@ -16,7 +16,7 @@ impl AiHandler {
     }
 }
 
-fn parse_resource(resource_name: &str) -> Option<Resource> {
+pub(crate) fn parse_resource(resource_name: &str) -> Option<Resource> {
     match resource_name {
         "food" => Some(Resource::Food),
         "linemate" => Some(Resource::Linemate),
@@ -36,6 +36,9 @@ impl HasId for AiHandler {
 }
 
 impl CommandHandler for AiHandler {
+    /// Unknown command names are an `InvalidAction`, known ones with missing, extra
+    /// or unknown arguments are `InvalidParameters`. Both are answered `ko`, the
+    /// spec has no other error for an AI, the split only shows in the logs
     fn validate_cmd(&self, cmd_name: &str, args: &str) -> EventType {
         let action = match (cmd_name, args.is_empty()) {
             // Commandes sans arguments
@@ -48,17 +51,23 @@ impl CommandHandler for AiHandler {
             ("Fork", true) => AIAction::Action(Fork),
             ("Eject", true) => AIAction::Action(Eject),
             ("Incantation", true) => AIAction::Action(Incantation),
+            ("Time", true) => AIAction::Action(Time),
 
             // Commandes avec arguments
             ("Broadcast", false) => AIAction::Action(Broadcast(args.to_string())),
             ("Take", false) => parse_resource(&args.to_lowercase())
-                .map_or(AIAction::Shared(SharedAction::InvalidAction), |res| {
+                .map_or(AIAction::Shared(SharedAction::InvalidParameters), |res| {
                     AIAction::Action(Take(res))
                 }),
             ("Set", false) => parse_resource(&args.to_lowercase())
-                .map_or(AIAction::Shared(SharedAction::InvalidAction), |res| {
+                .map_or(AIAction::Shared(SharedAction::InvalidParameters), |res| {
                     AIAction::Action(Set(res))
                 }),
+            (
+                "Forward" | "Right" | "Left" | "Look" | "Inventory" | "Connect_nbr" | "Fork"
+                | "Eject" | "Incantation" | "Time" | "Broadcast" | "Take" | "Set",
+                _,
+            ) => AIAction::Shared(SharedAction::InvalidParameters),
 
             // Cas par défaut
             _ => AIAction::Shared(SharedAction::InvalidAction),
@@ -93,6 +102,9 @@ impl CommandHandler for AiHandler {
                 AIResponse::Look(look_result) => {
                     CommandRes::Response(format!("{}\n", LookFormat(&look_result)))
                 }
+                AIResponse::Time(tick, freq) => {
+                    CommandRes::Response(format!("time {} {}\n", tick, freq))
+                }
             },
             ServerResponse::Gui(_) | ServerResponse::Pending(_) => {
                 unreachable!()
@@ -107,3 +119,87 @@ impl CommandHandler for AiHandler {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::catalog::ai_responses;
+
+    #[test]
+    fn test_graphic_is_an_unknown_command_once_logged_in() {
+        let mut handler = AiHandler::new(1);
+
+        assert!(matches!(
+            handler.parse_command("GRAPHIC".to_string()),
+            EventType::AI(AIEvent {
+                id: 1,
+                action: AIAction::Shared(SharedAction::InvalidAction),
+            })
+        ));
+    }
+
+    #[test]
+    fn test_responses_match_reference_format() {
+        let mut handler = AiHandler::new(1);
+
+        for (response, expected) in ai_responses() {
+            let label = format!("{:?}", response);
+            let is_dead = matches!(response, AIResponse::Dead);
+            match handler.handle_command(ServerResponse::AI(response)) {
+                CommandRes::Response(line) => assert_eq!(line, expected, "{}", label),
+                CommandRes::ChangeState(DEAD(line)) if is_dead => {
+                    assert_eq!(line, expected, "{}", label)
+                }
+                CommandRes::ChangeState(_) => panic!("{} changed state", label),
+            }
+        }
+    }
+
+    #[test]
+    fn test_commands_parse_to_expected_actions() {
+        let mut handler = AiHandler::new(1);
+
+        let cases = [
+            ("Forward", "Action(Forward)"),
+            ("Right", "Action(Right)"),
+            ("Left", "Action(Left)"),
+            ("Look", "Action(Look)"),
+            ("Inventory", "Action(Inventory)"),
+            ("Connect_nbr", "Action(ConnectNbr)"),
+            ("Fork", "Action(Fork)"),
+            ("Eject", "Action(Eject)"),
+            ("Incantation", "Action(Incantation)"),
+            ("Time", "Action(Time)"),
+            (
+                "Broadcast hello world",
+                "Action(Broadcast(\"hello world\"))",
+            ),
+            ("Take food", "Action(Take(Food))"),
+            ("Set thystame", "Action(Set(Thystame))"),
+            // The resource is lowercased, the command name is not
+            ("Take FOOD", "Action(Take(Food))"),
+            ("Set Linemate", "Action(Set(Linemate))"),
+            ("forward", "Shared(InvalidAction)"),
+            ("TAKE food", "Shared(InvalidAction)"),
+            ("Dance", "Shared(InvalidAction)"),
+            ("", "Shared(InvalidAction)"),
+            ("Forward extra", "Shared(InvalidParameters)"),
+            ("Incantation now", "Shared(InvalidParameters)"),
+            ("Take", "Shared(InvalidParameters)"),
+            ("Take ", "Shared(InvalidParameters)"),
+            ("Take gold", "Shared(InvalidParameters)"),
+            ("Set", "Shared(InvalidParameters)"),
+            ("Broadcast", "Shared(InvalidParameters)"),
+            ("Broadcast ", "Shared(InvalidParameters)"),
+        ];
+
+        for (command, expected) in cases {
+            match handler.parse_command(command.to_string()) {
+                EventType::AI(AIEvent { id: 1, action }) => {
+                    assert_eq!(format!("{:?}", action), expected, "{:?}", command)
+                }
+                other => panic!("{:?} parsed to {:?}", command, other),
+            }
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use crate::handler::ai::AiHandler;
+use crate::handler::command::{CommandHandler, CommandRes, State};
+use crate::handler::graphics::GraphicHandler;
+use crate::handler::login::LoginHandler;
+use crate::player::Direction;
+use crate::protocol::{
+    AIResponse, GUIResponse, GuiCategory, PendingResponse, ServerResponse, SharedResponse, TeamType,
+};
+use crate::resources::{ElevationLevel, Resource, Resources};
+use crate::vec2::UPosition;
+use std::fmt::Write;
+use std::sync::Arc;
+
+/// One example line per command a logged in AI can send
+pub(crate) const AI_COMMANDS: [&str; 13] = [
+    "Forward",
+    "Right",
+    "Left",
+    "Look",
+    "Inventory",
+    "Broadcast <text>",
+    "Connect_nbr",
+    "Fork",
+    "Eject",
+    "Take <resource>",
+    "Set <resource>",
+    "Incantation",
+    "Time",
+];
+
+/// One example line per command a gui can send
+pub(crate) const GUI_COMMANDS: [&str; 18] = [
+    "msz",
+    "bct <x> <y>",
+    "mct",
+    "tna",
+    "ppo #<id>",
+    "plv #<id>",
+    "pin #<id>",
+    "sgt",
+    "sst <freq>",
+    "dbg sched",
+    "dbg map",
+    "sub <category>",
+    "unsub <category>",
+    "pls",
+    "egl",
+    "kill #<id>",
+    "setr <x> <y> <resource> <amount>",
+    "tp #<id> <x> <y>",
+];
+
+/// Replaces the placeholders of a catalog line with a value the handlers accept
+pub(crate) fn example_command(command: &str) -> String {
+    command
+        .replace("<text>", "hello world")
+        .replace("<resource>", "food")
+        .replace("<x> <y>", "1 2")
+        .replace("<amount>", "3")
+        .replace("<id>", "4")
+        .replace("<freq>", "100")
+        .replace("<category>", "positions")
+}
+
+fn sample_resources() -> Resources {
+    Resources::builder()
+        .food(1)
+        .linemate(2)
+        .deraumere(3)
+        .sibur(4)
+        .mendiane(5)
+        .phiras(6)
+        .thystame(7)
+        .build()
+}
+
+/// Every AI response with the exact bytes of the reference format
+pub(crate) fn ai_responses() -> Vec<(AIResponse, &'static str)> {
+    let resources = Resources::builder().food(10).linemate(1).build();
+
+    vec![
+        (AIResponse::Shared(SharedResponse::Ok), "ok\n"),
+        (AIResponse::Shared(SharedResponse::Ko), "ko\n"),
+        (AIResponse::Dead, "dead\n"),
+        (
+            AIResponse::Broadcast(3, Arc::new("hello world".into())),
+            "message 3, hello world\n",
+        ),
+        (AIResponse::Incantating, "Elevation underway\n"),
+        (
+            AIResponse::LevelUp(ElevationLevel::Level2),
+            "Current level: 2\n",
+        ),
+        (
+            AIResponse::Inventory(resources.clone()),
+            "[food 10, linemate 1, deraumere 0, sibur 0, mendiane 0, phiras 0, thystame 0]\n",
+        ),
+        (AIResponse::ConnectNbr(3), "3\n"),
+        (AIResponse::Eject(5), "eject 5\n"),
+        (
+            AIResponse::Look(vec![
+                (1, 0, Resources::new()),
+                (0, 0, resources),
+                (2, 0, Resources::new()),
+            ]),
+            "[player, food food food food food food food food food food linemate, player player]\n",
+        ),
+        (AIResponse::Time(12, 100), "time 12 100\n"),
+    ]
+}
+
+/// Every gui response with the exact bytes of the reference format
+pub(crate) fn gui_responses() -> Vec<(GUIResponse, &'static str)> {
+    let resources = sample_resources();
+
+    vec![
+        (GUIResponse::Msz(UPosition::new(10, 20)), "msz 10 20\n"),
+        (
+            GUIResponse::Bct((UPosition::new(1, 2), resources.clone())),
+            "bct 1 2 1 2 3 4 5 6 7\n",
+        ),
+        (
+            GUIResponse::Mct(vec![
+                (UPosition::new(0, 0), Resources::new()),
+                (UPosition::new(1, 0), resources.clone()),
+            ]),
+            "bct 0 0 0 0 0 0 0 0 0\nbct 1 0 1 2 3 4 5 6 7\n",
+        ),
+        (
+            GUIResponse::Tna(vec!["team1".into(), "team2".into()]),
+            "tna team1\ntna team2\n",
+        ),
+        (
+            GUIResponse::Pnw(
+                4,
+                UPosition::new(3, 5),
+                Direction::West,
+                ElevationLevel::Level1,
+                "team1".into(),
+            ),
+            "pnw #4 3 5 4 1 team1\n",
+        ),
+        (
+            GUIResponse::Ppo(4, UPosition::new(3, 5), Direction::North),
+            "ppo #4 3 5 1\n",
+        ),
+        (GUIResponse::Plv(4, ElevationLevel::Level8), "plv #4 8\n"),
+        (
+            GUIResponse::Pin(4, UPosition::new(3, 5), resources),
+            "pin #4 3 5 1 2 3 4 5 6 7\n",
+        ),
+        (
+            GUIResponse::Pic(UPosition::new(2, 2), ElevationLevel::Level2, vec![4, 7]),
+            "pic 2 2 2 #4 #7\n",
+        ),
+        (GUIResponse::Pie(UPosition::new(2, 2), true), "pie 2 2 1\n"),
+        (GUIResponse::Pie(UPosition::new(2, 2), false), "pie 2 2 0\n"),
+        (GUIResponse::Pfk(4), "pfk #4\n"),
+        (GUIResponse::Pdr(4, Resource::Thystame), "pdr #4 6\n"),
+        (GUIResponse::Pgt(4, Resource::Food), "pgt #4 0\n"),
+        (GUIResponse::Pdi(4), "pdi #4\n"),
+        (
+            GUIResponse::Enw(9, Some(4), UPosition::new(3, 5)),
+            "enw #9 #4 3 5\n",
+        ),
+        (
+            GUIResponse::Enw(2, None, UPosition::new(0, 1)),
+            "enw #2 #-1 0 1\n",
+        ),
+        (GUIResponse::Ebo(9), "ebo #9\n"),
+        (GUIResponse::Edi(9), "edi #9\n"),
+        (GUIResponse::Sgt(100), "sgt 100\n"),
+        (GUIResponse::Sst(50), "sst 50\n"),
+        (GUIResponse::Seg("team1".into()), "seg team1\n"),
+        (
+            GUIResponse::Smg(Arc::new("hello world".into())),
+            "smg hello world\n",
+        ),
+        (
+            GUIResponse::Pbc(4, Arc::new("hello world".into())),
+            "pbc #4 hello world\n",
+        ),
+        (GUIResponse::Pex(4), "pex #4\n"),
+        (GUIResponse::Shared(SharedResponse::Ko), "suc\n"),
+        (GUIResponse::Sbp, "sbp\n"),
+        (GUIResponse::Dbg(vec!["tick 0".into()]), "dbg tick 0\n"),
+        (
+            GUIResponse::Subscribe(GuiCategory::Positions, false),
+            "unsub positions\n",
+        ),
+    ]
+}
+
+/// Bytes a handler writes for `response`, whether or not it changes state
+pub(crate) fn render(handler: &mut dyn CommandHandler, response: ServerResponse) -> String {
+    match handler.handle_command(response) {
+        CommandRes::Response(line) => line,
+        CommandRes::ChangeState(State::IA(line) | State::DEAD(line)) => line,
+        CommandRes::ChangeState(State::GUI) => String::new(),
+    }
+}
+
+/// Message catalog printed by `--dump-protocol`, rendered by the real handlers
+pub fn dump() -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(out, "# login commands");
+    let _ = writeln!(out, "<team name>");
+    let _ = writeln!(out, "GRAPHIC");
+    let _ = writeln!(out, "# login responses");
+    let mut login = LoginHandler::new(0);
+    out.push_str(&render(
+        &mut login,
+        ServerResponse::Pending(PendingResponse::LogAs(TeamType::IA(
+            3,
+            UPosition::new(10, 10),
+        ))),
+    ));
+    out.push_str(&render(
+        &mut login,
+        ServerResponse::Pending(PendingResponse::Shared(SharedResponse::Ko)),
+    ));
+
+    let _ = writeln!(out, "# ai commands");
+    for command in AI_COMMANDS {
+        let _ = writeln!(out, "{}", command);
+    }
+    let _ = writeln!(out, "# ai responses");
+    let mut ai = AiHandler::new(0);
+    for (response, _) in ai_responses() {
+        out.push_str(&render(&mut ai, ServerResponse::AI(response)));
+    }
+
+    let _ = writeln!(out, "# gui commands");
+    for command in GUI_COMMANDS {
+        let _ = writeln!(out, "{}", command);
+    }
+    let _ = writeln!(out, "# gui responses");
+    let mut gui = GraphicHandler::new(0);
+    for (response, _) in gui_responses() {
+        out.push_str(&render(&mut gui, ServerResponse::Gui(response)));
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalog_commands_are_understood() {
+        let mut ai = AiHandler::new(1);
+        let mut gui = GraphicHandler::new(1);
+
+        for command in AI_COMMANDS {
+            let event = ai.parse_command(example_command(command));
+            assert!(!format!("{:?}", event).contains("Invalid"), "{}", command);
+        }
+        for command in GUI_COMMANDS {
+            let event = gui.parse_command(example_command(command));
+            assert!(!format!("{:?}", event).contains("Invalid"), "{}", command);
+        }
+    }
+
+    #[test]
+    fn test_dump_lists_every_response() {
+        let dump = dump();
+
+        assert!(dump.starts_with("# login commands\n<team name>\nGRAPHIC\n# login responses\n"));
+        assert_eq!(dump.matches("GRAPHIC").count(), 1);
+        assert!(dump.contains("# ai responses\nok\nko\ndead\n"));
+        for (_, expected) in gui_responses() {
+            assert!(dump.contains(expected), "{:?}", expected);
+        }
+    }
+}
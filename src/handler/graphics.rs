@@ -1,11 +1,13 @@
 use crate::formater::{BctFormat, IdFormat, PinFormat, ResourceFormat};
 use crate::formater::{LevelFormat, UVecFormat};
+use crate::handler::ai::parse_resource;
 use crate::handler::command::{CommandHandler, CommandRes, Handler};
 use crate::protocol::{
-    EventType, GUIAction, GUIEvent, GUIResponse, HasId, Id, ServerResponse, SharedAction,
-    SharedResponse, parse_prefixed_id,
+    DebugTopic, EventType, GUIAction, GUIEvent, GUIResponse, GuiCategory, HasId, Id,
+    ServerResponse, SharedAction, SharedResponse, parse_prefixed_id,
 };
 use crate::vec2::UPosition;
+use std::fmt::Write;
 
 pub struct GraphicHandler(Handler);
 
@@ -15,6 +17,58 @@ impl GraphicHandler {
     }
 }
 
+const CATEGORY_NAMES: [(&str, GuiCategory); 4] = [
+    ("positions", GuiCategory::Positions),
+    ("inventories", GuiCategory::Inventories),
+    ("resources", GuiCategory::Resources),
+    ("incantations", GuiCategory::Incantations),
+];
+
+fn parse_category(name: &str) -> Option<GuiCategory> {
+    CATEGORY_NAMES
+        .iter()
+        .find(|(category_name, _)| *category_name == name)
+        .map(|(_, category)| *category)
+}
+
+fn parse_position(x: &str, y: &str) -> Option<UPosition> {
+    Some(UPosition::new(x.parse().ok()?, y.parse().ok()?))
+}
+
+/// `x y` followed by at least one `resource amount` pair
+fn parse_setr(args: &str) -> Option<GUIAction> {
+    let parts: Vec<&str> = args.split_whitespace().collect();
+    if parts.len() < 4 || parts.len() % 2 != 0 {
+        return None;
+    }
+    let pos = parse_position(parts[0], parts[1])?;
+    let amounts = parts[2..]
+        .chunks(2)
+        .map(|pair| Some((parse_resource(pair[0])?, pair[1].parse().ok()?)))
+        .collect::<Option<Vec<_>>>()?;
+    Some(GUIAction::Setr(pos, amounts))
+}
+
+/// `#id x y`
+fn parse_tp(args: &str) -> Option<GUIAction> {
+    let parts: Vec<&str> = args.split_whitespace().collect();
+    let [id, x, y] = parts.as_slice() else {
+        return None;
+    };
+    Some(GUIAction::Tp(
+        parse_prefixed_id(id, '#')?,
+        parse_position(x, y)?,
+    ))
+}
+
+fn category_name(category: GuiCategory) -> &'static str {
+    CATEGORY_NAMES
+        .iter()
+        .find(|(_, c)| *c == category)
+        .map(|(name, _)| *name)
+        .unwrap()
+}
+
 impl HasId for GraphicHandler {
     fn id(&self) -> Id {
         self.0.id
@@ -92,6 +146,45 @@ impl CommandHandler for GraphicHandler {
                     GUIAction::Shared(SharedAction::InvalidParameters)
                 }
             }
+            // Non-spec debug commands, all namespaced under `dbg`
+            "dbg" => match args.trim() {
+                "sched" => GUIAction::Dbg(DebugTopic::Scheduler),
+                "map" => GUIAction::Dbg(DebugTopic::Map),
+                _ => GUIAction::Shared(SharedAction::InvalidParameters),
+            },
+            // Non-spec roster, one `pnw` per live player
+            "pls" => {
+                if args.is_empty() {
+                    GUIAction::Pls
+                } else {
+                    GUIAction::Shared(SharedAction::InvalidParameters)
+                }
+            }
+            // Non-spec egg list, one `enw` per live egg
+            "egl" => {
+                if args.is_empty() {
+                    GUIAction::Egl
+                } else {
+                    GUIAction::Shared(SharedAction::InvalidParameters)
+                }
+            }
+            // Non-spec admin commands
+            "kill" => {
+                if let Some(id) = parse_prefixed_id(args, '#') {
+                    GUIAction::Kill(id)
+                } else {
+                    GUIAction::Shared(SharedAction::InvalidParameters)
+                }
+            }
+            "setr" => {
+                parse_setr(args).unwrap_or(GUIAction::Shared(SharedAction::InvalidParameters))
+            }
+            "tp" => parse_tp(args).unwrap_or(GUIAction::Shared(SharedAction::InvalidParameters)),
+            // Non-spec update filters
+            "sub" | "unsub" => match parse_category(args.trim()) {
+                Some(category) => GUIAction::Subscribe(category, cmd_name == "sub"),
+                None => GUIAction::Shared(SharedAction::InvalidParameters),
+            },
             &_ => GUIAction::Shared(SharedAction::InvalidAction),
         };
 
@@ -114,11 +207,11 @@ impl CommandHandler for GraphicHandler {
                 }
                 GUIResponse::Bct(bct) => CommandRes::Response(format!("{}\n", BctFormat(&bct))),
                 GUIResponse::Mct(mct) => {
-                    let formated_mct = mct
-                        .iter()
-                        .map(|bct| format!("{}\n", BctFormat(bct)))
-                        .collect::<Vec<String>>()
-                        .join("");
+                    // A single buffer, a big map has hundreds of thousands of lines
+                    let mut formated_mct = String::with_capacity(mct.len() * 24);
+                    for bct in &mct {
+                        let _ = writeln!(formated_mct, "{}", BctFormat(bct));
+                    }
                     CommandRes::Response(formated_mct)
                 }
                 GUIResponse::Tna(team_names) => {
@@ -198,10 +291,11 @@ impl CommandHandler for GraphicHandler {
                 GUIResponse::Pdi(player_id) => {
                     CommandRes::Response(format!("pdi {}\n", IdFormat(&player_id)))
                 }
+                // Eggs laid at start have no layer, written `#-1` as the reference server does
                 GUIResponse::Enw(egg_id, player_id, egg_pos) => CommandRes::Response(format!(
                     "enw {} {} {}\n",
                     IdFormat(&egg_id),
-                    IdFormat(&player_id),
+                    player_id.map_or("#-1".to_string(), |id| IdFormat(&id).to_string()),
                     UVecFormat(&egg_pos)
                 )),
                 GUIResponse::Ebo(egg_id) => {
@@ -212,6 +306,19 @@ impl CommandHandler for GraphicHandler {
                 }
                 GUIResponse::Seg(team_name) => CommandRes::Response(format!("seg {}\n", team_name)),
                 GUIResponse::Smg(message) => CommandRes::Response(format!("smg {}\n", message)),
+                GUIResponse::Subscribe(category, subscribed) => CommandRes::Response(format!(
+                    "{} {}\n",
+                    if subscribed { "sub" } else { "unsub" },
+                    category_name(category)
+                )),
+                GUIResponse::Dbg(lines) => {
+                    let formated_lines = lines
+                        .iter()
+                        .map(|line| format!("dbg {}\n", line))
+                        .collect::<Vec<String>>()
+                        .join("");
+                    CommandRes::Response(formated_lines)
+                }
             },
             ServerResponse::AI(_) | ServerResponse::Pending(_) => {
                 unreachable!()
@@ -226,3 +333,46 @@ impl CommandHandler for GraphicHandler {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handler::catalog::{gui_responses, render};
+
+    #[test]
+    fn test_responses_match_reference_format() {
+        let mut handler = GraphicHandler::new(1);
+
+        for (response, expected) in gui_responses() {
+            let label = format!("{:?}", response);
+            assert_eq!(
+                render(&mut handler, ServerResponse::Gui(response)),
+                expected,
+                "{}",
+                label
+            );
+        }
+    }
+
+    #[test]
+    fn test_malformed_admin_commands_are_bad_parameters() {
+        let mut handler = GraphicHandler::new(1);
+
+        for command in [
+            "setr 1 2",
+            "setr 1 2 food",
+            "setr 1 2 gold 3",
+            "setr 1 y food 3",
+            "tp #4 1",
+            "tp #4 1 2 3",
+            "kill #four",
+        ] {
+            let event = handler.parse_command(command.to_string());
+            assert!(
+                format!("{:?}", event).contains("InvalidParameters"),
+                "{}",
+                command
+            );
+        }
+    }
+}
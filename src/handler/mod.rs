@@ -1,4 +1,5 @@
 pub mod ai;
+pub(crate) mod catalog;
 pub mod command;
 pub(crate) mod graphics;
 pub mod login;
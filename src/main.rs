@@ -1,4 +1,5 @@
 #![allow(dead_code)]
+#![allow(clippy::upper_case_acronyms)]
 
 mod cell;
 mod connection;
@@ -6,6 +7,7 @@ mod constant;
 mod egg;
 mod event;
 mod formater;
+mod game;
 mod gui;
 mod handler;
 mod map;
@@ -14,17 +16,24 @@ mod player;
 mod protocol;
 mod resources;
 mod server;
+mod simulation;
 mod sound;
 mod team;
 mod vec2;
 
 use crate::server::{Server, ServerConfig};
+use crate::simulation::{Simulation, parse_script};
 use std::error::Error;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     env_logger::init();
 
+    if std::env::args().any(|arg| arg == "--dump-protocol") {
+        print!("{}", handler::catalog::dump());
+        return Ok(());
+    }
+
     let server_config = ServerConfig::new(
         "0.0.0.0".to_string(),
         4242,
@@ -39,6 +48,23 @@ async fn main() -> Result<(), Box<dyn Error>> {
         4,
         100,
     );
+
+    // Headless run of scripted AIs: `--simulate <script> <ticks>`
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(index) = args.iter().position(|arg| arg == "--simulate") {
+        let (Some(path), Some(ticks)) = (args.get(index + 1), args.get(index + 2)) else {
+            return Err("usage: --simulate <script> <ticks>".into());
+        };
+        let ticks: u64 = ticks.parse()?;
+        let players = parse_script(&std::fs::read_to_string(path)?)?;
+        let mut simulation = Simulation::from_config(&server_config)?;
+        for team in simulation.add_script(&players).await {
+            eprintln!("Login refused for team {}", team);
+        }
+        print!("{}", simulation.run(ticks).await);
+        return Ok(());
+    }
+
     let mut server = Server::from_config(server_config).await?;
     server.run().await?;
     Ok(())
@@ -1,95 +1,137 @@
 use crate::cell::Cell;
 use crate::egg::Egg;
 use crate::gui::Gui;
-use crate::protocol::{ClientSender, GUIResponse, Id, ServerResponse};
+use crate::protocol::{GUIResponse, HasId, Id};
 use crate::resources::{Resource, Resources};
-use crate::vec2::{HasPosition, Position, Size, UPosition};
+use crate::vec2::{Position, Size, UPosition};
 use rand::Rng;
-use std::collections::HashMap;
+use rand::seq::SliceRandom;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::ops::{Index, IndexMut};
 use std::sync::atomic::{AtomicU64, Ordering};
 
 pub struct Map {
     size: Size,
-    map: Vec<Vec<Cell>>,
+    cells: Cells,
     resources: Resources,
-    eggs: Vec<Egg>,
+    egg_positions: BTreeMap<Id, UPosition>,
 }
 
+/// Storage of the tiles, sparse maps only keep the non-empty ones
+enum Cells {
+    Dense(Vec<Vec<Cell>>),
+    Sparse(HashMap<UPosition, Cell>),
+}
+
+/// What a sparse map hands out for the tiles it doesn't store
+static EMPTY_CELL: Cell = Cell::new();
+
 impl Index<UPosition> for Map {
     type Output = Cell;
 
     fn index(&self, pos: UPosition) -> &Self::Output {
-        &self.map[pos.y() as usize][pos.x() as usize]
+        self.get(pos).expect("position outside of the map")
     }
 }
 
 impl IndexMut<UPosition> for Map {
     fn index_mut(&mut self, pos: UPosition) -> &mut Self::Output {
-        &mut self.map[pos.y() as usize][pos.x() as usize]
+        self.get_mut(pos).expect("position outside of the map")
     }
 }
 
+/// How the initial eggs of the teams are laid on the map
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum EggPlacement {
+    /// Every egg on an independent random tile, as the spec does
+    #[default]
+    Random,
+    /// Eggs evenly spaced over the map, teams interleaved, one egg per tile while the map is big enough
+    Balanced,
+}
+
 pub enum IncantationError {
     NotEnoughPlayers,
     NotEnoughRessources,
 }
 
-pub struct CellIter<'a> {
-    outer: std::slice::Iter<'a, Vec<Cell>>,
-    inner: Option<std::slice::Iter<'a, Cell>>,
-}
-
-impl<'a> Iterator for CellIter<'a> {
-    type Item = &'a Cell;
+impl Map {
+    pub fn new(size: Size) -> Self {
+        Self::with_cells(
+            size,
+            Cells::Dense(vec![
+                vec![Cell::new(); size.x() as usize];
+                size.y() as usize
+            ]),
+        )
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            if let Some(inner_iter) = &mut self.inner {
-                if let Some(cell) = inner_iter.next() {
-                    return Some(cell);
-                }
-            }
-            self.inner = self.outer.next().map(|row| row.iter());
-            self.inner.as_ref()?;
-        }
+    /// Map storing only its non-empty tiles, lighter for big mostly empty maps
+    /// but slower to access than the dense one
+    pub fn new_sparse(size: Size) -> Self {
+        Self::with_cells(size, Cells::Sparse(HashMap::new()))
     }
-}
 
-impl Map {
-    pub fn new(size: Size) -> Self {
+    fn with_cells(size: Size, cells: Cells) -> Self {
         Map {
             size,
-            map: vec![vec![Cell::new(); size.x() as usize]; size.y() as usize],
+            cells,
             resources: Default::default(),
-            eggs: Default::default(),
+            egg_positions: Default::default(),
         }
     }
 
-    pub fn cells(&self) -> CellIter {
-        CellIter {
-            outer: self.map.iter(),
-            inner: None,
-        }
+    /// Every tile in row-major order, empty ones included
+    pub fn cells(&self) -> impl Iterator<Item = &Cell> {
+        self.cells_with_positions().map(|(_, cell)| cell)
     }
 
     pub fn cells_with_positions(&self) -> impl Iterator<Item = (UPosition, &Cell)> {
-        self.map.iter().enumerate().flat_map(|(y, row)| {
-            row.iter()
-                .enumerate()
-                .map(move |(x, cell)| (UPosition::new(x as u64, y as u64), cell))
-        })
+        let width = self.size.x();
+        (0..self.size.y())
+            .flat_map(move |y| (0..width).map(move |x| UPosition::new(x, y)))
+            .map(|pos| (pos, &self[pos]))
+    }
+
+    /// Number of tiles actually held in memory
+    pub fn stored_cells(&self) -> usize {
+        match &self.cells {
+            Cells::Dense(rows) => rows.iter().map(Vec::len).sum(),
+            Cells::Sparse(cells) => cells.len(),
+        }
+    }
+
+    fn contains(&self, pos: UPosition) -> bool {
+        pos.x() < self.size.x() && pos.y() < self.size.y()
     }
 
     pub fn get(&self, pos: UPosition) -> Option<&Cell> {
-        self.map.get(pos.y() as usize)?.get(pos.x() as usize)
+        match &self.cells {
+            Cells::Dense(rows) => rows.get(pos.y() as usize)?.get(pos.x() as usize),
+            Cells::Sparse(cells) => self
+                .contains(pos)
+                .then(|| cells.get(&pos).unwrap_or(&EMPTY_CELL)),
+        }
     }
 
     pub fn get_mut(&mut self, pos: UPosition) -> Option<&mut Cell> {
-        self.map
-            .get_mut(pos.y() as usize)?
-            .get_mut(pos.x() as usize)
+        if !self.contains(pos) {
+            return None;
+        }
+        match &mut self.cells {
+            Cells::Dense(rows) => rows.get_mut(pos.y() as usize)?.get_mut(pos.x() as usize),
+            Cells::Sparse(cells) => Some(cells.entry(pos).or_insert_with(Cell::new)),
+        }
+    }
+
+    /// Forgets a sparse tile once it holds nothing anymore
+    fn prune(&mut self, pos: UPosition) {
+        if let Cells::Sparse(cells) = &mut self.cells {
+            if cells.get(&pos).is_some_and(Cell::is_empty) {
+                cells.remove(&pos);
+            }
+        }
     }
 
     pub fn get_pos(&self, pos: UPosition) -> UPosition {
@@ -100,21 +142,11 @@ impl Map {
     }
 
     pub fn get_pos_with_offset(&self, pos: UPosition, offset: Position) -> UPosition {
-        let new_x = (pos.x() as i64 + offset.x()).rem_euclid(self.size.x() as i64) as u64;
-        let new_y = (pos.y() as i64 + offset.y()).rem_euclid(self.size.y() as i64) as u64;
-
-        UPosition::new(new_x, new_y)
+        pos.wrapping_add(offset, self.size)
     }
 
     pub fn get_pos_signed(&self, pos: Position) -> UPosition {
-        fn wrap(value: i64, max: u64) -> u64 {
-            ((value % max as i64 + max as i64) % max as i64) as u64
-        }
-
-        let wrapped_x = wrap(pos.x(), self.size.x());
-        let wrapped_y = wrap(pos.y(), self.size.y());
-
-        UPosition::new(wrapped_x, wrapped_y)
+        UPosition::default().wrapping_add(pos, self.size)
     }
 
     pub fn size(&self) -> UPosition {
@@ -130,71 +162,103 @@ impl Map {
     }
 
     pub fn nb_eggs_by_team(&self, team_id: Id) -> u64 {
-        self.eggs.iter().filter(|egg| egg.team_id() == team_id).count() as u64
+        self.eggs().filter(|egg| egg.team_id() == team_id).count() as u64
     }
 
     pub fn spawn_egg(&mut self, team_id: Id, pos: UPosition) -> Id {
+        self.add_egg(team_id, pos, None)
+    }
+
+    /// Egg forked by a player
+    pub fn lay_egg(&mut self, team_id: Id, pos: UPosition, layer: Id) -> Id {
+        self.add_egg(team_id, pos, Some(layer))
+    }
+
+    fn add_egg(&mut self, team_id: Id, pos: UPosition, layer: Option<Id>) -> Id {
         static EGG_ID: AtomicU64 = AtomicU64::new(0);
         let egg_id: Id = EGG_ID.fetch_add(1, Ordering::Relaxed);
-        let new_egg = Egg::new(egg_id, team_id, pos);
-        self.eggs.push(new_egg);
+        let new_egg = Egg::new(egg_id, team_id, pos, layer);
+        self[pos].add_egg(new_egg);
+        self.egg_positions.insert(egg_id, pos);
         egg_id
     }
 
-    pub fn spawn_eggs(&mut self, team_id: Id, amount: u64) {
+    /// All eggs, ordered by id
+    pub fn eggs(&self) -> impl Iterator<Item = &Egg> {
+        self.egg_positions
+            .iter()
+            .filter_map(|(egg_id, pos)| self[*pos].eggs().iter().find(|egg| egg.id() == *egg_id))
+    }
+
+    pub fn eggs_at_pos(&self, pos: UPosition) -> impl Iterator<Item = &Egg> {
+        self[pos].eggs().iter()
+    }
+
+    pub fn spawn_eggs(&mut self, team_id: Id, amount: u64, rng: &mut impl Rng) {
         (0..amount).for_each(|_| {
-            let x = rand::rng().random_range(0..self.size.x());
-            let y = rand::rng().random_range(0..self.size.y());
+            let x = rng.random_range(0..self.size.x());
+            let y = rng.random_range(0..self.size.y());
             let pos = UPosition::new(x, y);
             self.spawn_egg(team_id, pos);
         });
     }
 
-    pub fn drop_egg(&mut self, team_id: Id) -> Option<Egg> {
-        let egg_positions: Vec<usize> = self
-            .eggs
-            .iter()
-            .enumerate()
-            .filter_map(|(pos, egg)| {
-                if egg.team_id() == team_id {
-                    Some(pos)
-                } else {
-                    None
-                }
+    pub fn spawn_eggs_balanced(&mut self, team_ids: &[Id], amount: u64, rng: &mut impl Rng) {
+        let nb_eggs = team_ids.len() as u64 * amount;
+        if nb_eggs == 0 {
+            return;
+        }
+        let nb_tiles = self.size.x() * self.size.y();
+        let step = nb_tiles as f64 / nb_eggs as f64;
+        let offset = rng.random_range(0.0..step.max(1.0));
+        let mut positions: Vec<UPosition> = (0..nb_eggs)
+            .map(|i| {
+                let index = (offset + i as f64 * step) as u64 % nb_tiles;
+                UPosition::new(index % self.size.x(), index / self.size.x())
             })
             .collect();
+        positions.shuffle(rng);
+
+        for (i, pos) in positions.into_iter().enumerate() {
+            self.spawn_egg(team_ids[i % team_ids.len()], pos);
+        }
+    }
+
+    /// Removes a random egg of the team, the new player spawns on its tile
+    pub fn drop_egg(&mut self, team_id: Id, rng: &mut impl Rng) -> Option<Egg> {
+        let team_eggs: Vec<Id> = self
+            .eggs()
+            .filter(|egg| egg.team_id() == team_id)
+            .map(|egg| egg.id())
+            .collect();
 
-        if egg_positions.is_empty() {
+        if team_eggs.is_empty() {
             return None;
         }
 
-        let mut rng = rand::rng();
-        let random_index = rng.random_range(0..egg_positions.len());
-        let position_to_remove = egg_positions[random_index];
+        let random_index = rng.random_range(0..team_eggs.len());
+        let egg_id = team_eggs[random_index];
+        let pos = self.egg_positions.remove(&egg_id)?;
+        let egg = self[pos].remove_egg(egg_id);
+        self.prune(pos);
+        egg
+    }
 
-        Some(self.eggs.remove(position_to_remove))
+    /// Removes an egg wherever it is, `None` if it already hatched or broke
+    pub fn remove_egg(&mut self, egg_id: Id) -> Option<Egg> {
+        let pos = self.egg_positions.remove(&egg_id)?;
+        let egg = self[pos].remove_egg(egg_id);
+        self.prune(pos);
+        egg
     }
 
     pub fn break_eggs_at_pos(&mut self, pos: UPosition) -> Vec<Egg> {
-        let eggs_to_remove: Vec<usize> = self
-            .eggs
-            .iter()
-            .enumerate()
-            .filter_map(|(index, egg)| {
-                if egg.position() == pos {
-                    Some(index)
-                } else {
-                    None
-                }
-            })
-            .collect();
-
-        let mut removed_eggs = Vec::with_capacity(eggs_to_remove.len());
-        for &index in eggs_to_remove.iter().rev() {
-            removed_eggs.push(self.eggs.remove(index));
+        let broken_eggs = self[pos].take_eggs();
+        for egg in &broken_eggs {
+            self.egg_positions.remove(&egg.id());
         }
-        removed_eggs.reverse();
-        removed_eggs
+        self.prune(pos);
+        broken_eggs
     }
 
     pub fn add_resource(
@@ -209,13 +273,61 @@ impl Map {
 
         //gui
         for (.., gui) in guis {
-            gui.send_to_client(ServerResponse::Gui(GUIResponse::Bct((
-                pos,
-                self[pos].ressources().clone(),
-            ))));
+            gui.notify(GUIResponse::Bct((pos, self[pos].ressources().clone())));
         }
     }
 
+    /// Sets the amount of each listed resource on the tile, the others are kept
+    pub fn set_resources(
+        &mut self,
+        pos: UPosition,
+        amounts: &[(Resource, u64)],
+        guis: &mut HashMap<Id, Gui>,
+    ) {
+        for &(resource, amount) in amounts {
+            let current = self[pos].ressources()[resource];
+            self.resources[resource] -= current;
+            self.resources[resource] += amount;
+            if amount > current {
+                self[pos].add_resource(resource, amount - current);
+            } else {
+                self[pos].del_resource(resource, current - amount);
+            }
+        }
+        self.prune(pos);
+
+        //gui
+        for (.., gui) in guis {
+            gui.notify(GUIResponse::Bct((pos, self[pos].ressources().clone())));
+        }
+    }
+
+    /// Removes all of `required` from the tile, or nothing if the tile lacks any of it
+    pub fn try_consume(
+        &mut self,
+        pos: UPosition,
+        required: &Resources,
+        guis: &mut HashMap<Id, Gui>,
+    ) -> bool {
+        if !self[pos].ressources().has_at_least(required) {
+            return false;
+        }
+        for resource in Resource::iter() {
+            let amount = required[resource];
+            if amount > 0 {
+                self[pos].del_resource(resource, amount);
+                self.resources[resource] -= amount;
+            }
+        }
+        self.prune(pos);
+
+        //gui
+        for (.., gui) in guis {
+            gui.notify(GUIResponse::Bct((pos, self[pos].ressources().clone())));
+        }
+        true
+    }
+
     pub fn del_resource(
         &mut self,
         resource: Resource,
@@ -226,15 +338,15 @@ impl Map {
         let res = self[pos].del_resource(resource, amount);
         if let Some(res) = res {
             self.resources[resource] -= amount;
+            self.prune(pos);
             //gui
             for (.., gui) in guis {
-                gui.send_to_client(ServerResponse::Gui(GUIResponse::Bct((
-                    pos,
-                    self[pos].ressources().clone(),
-                ))));
+                gui.notify(GUIResponse::Bct((pos, self[pos].ressources().clone())));
             }
             Some(res)
         } else {
+            // Reading the tile through IndexMut stored it, even if it's empty
+            self.prune(pos);
             None
         }
     }
@@ -242,12 +354,158 @@ impl Map {
 
 impl fmt::Display for Map {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for row in &self.map {
-            for cell in row {
-                write!(f, "{}", cell)?;
+        for (pos, cell) in self.cells_with_positions() {
+            write!(f, "{}", cell)?;
+            if pos.x() + 1 == self.size.x() {
+                writeln!(f)?;
             }
-            writeln!(f)?;
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec2::HasPosition;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn test_break_eggs_at_pos() {
+        let mut map = Map::new(Size::new(5, 5));
+        let pos = UPosition::new(2, 3);
+        let first = map.spawn_egg(0, pos);
+        let other_tile = map.spawn_egg(0, UPosition::new(3, 2));
+        let second = map.spawn_egg(1, pos);
+
+        let broken: Vec<Id> = map.break_eggs_at_pos(pos).iter().map(|e| e.id()).collect();
+
+        assert_eq!(broken, vec![first, second]);
+        assert_eq!(map.eggs_at_pos(pos).count(), 0);
+        assert_eq!(
+            map.eggs().map(|e| e.id()).collect::<Vec<_>>(),
+            vec![other_tile]
+        );
+        assert!(map.break_eggs_at_pos(pos).is_empty());
+    }
+
+    #[test]
+    fn test_cell_owns_its_eggs() {
+        let mut map = Map::new(Size::new(5, 5));
+        let pos = UPosition::new(4, 0);
+        let egg_id = map.spawn_egg(1, pos);
+
+        assert_eq!(map[pos].eggs().len(), 1);
+        assert_eq!(map[pos].eggs()[0].id(), egg_id);
+        assert!(map[UPosition::new(0, 4)].eggs().is_empty());
+        assert!(map[pos].to_string().ends_with(&format!("{}\x1b[0m)", 1)));
+    }
+
+    #[test]
+    fn test_drop_egg_updates_position_index() {
+        let mut map = Map::new(Size::new(5, 5));
+        let mut rng = StdRng::seed_from_u64(0);
+        let pos = UPosition::new(1, 1);
+        map.spawn_egg(0, pos);
+        let other_team = map.spawn_egg(1, pos);
+
+        let dropped = map.drop_egg(0, &mut rng).unwrap();
+
+        assert_eq!(dropped.position(), pos);
+        assert_eq!(
+            map.eggs_at_pos(pos).map(|e| e.id()).collect::<Vec<_>>(),
+            vec![other_team]
+        );
+        assert!(map.drop_egg(0, &mut rng).is_none());
+        assert_eq!(map.nb_eggs_by_team(1), 1);
+    }
+
+    #[test]
+    fn test_try_consume_is_all_or_nothing() {
+        let mut map = Map::new(Size::new(5, 5));
+        let mut guis = HashMap::new();
+        let pos = UPosition::new(1, 2);
+        map.add_resource(Resource::Linemate, 2, pos, &mut guis);
+        map.add_resource(Resource::Sibur, 1, pos, &mut guis);
+
+        let too_much = Resources::builder().linemate(1).sibur(2).build();
+        assert!(!map.try_consume(pos, &too_much, &mut guis));
+        assert_eq!(map[pos].ressources()[Resource::Linemate], 2);
+        assert_eq!(map.resources()[Resource::Sibur], 1);
+
+        let required = Resources::builder().linemate(1).sibur(1).build();
+        assert!(map.try_consume(pos, &required, &mut guis));
+        assert_eq!(map[pos].ressources()[Resource::Linemate], 1);
+        assert_eq!(map[pos].ressources()[Resource::Sibur], 0);
+        assert_eq!(map.resources()[Resource::Linemate], 1);
+        assert_eq!(map.resources()[Resource::Sibur], 0);
+    }
+
+    #[test]
+    fn test_balanced_eggs_do_not_share_tiles() {
+        let mut map = Map::new(Size::new(10, 10));
+        let mut rng = StdRng::seed_from_u64(3);
+        map.spawn_eggs_balanced(&[0, 1, 2], 6, &mut rng);
+
+        assert_eq!(map.nb_eggs_by_team(0), 6);
+        assert_eq!(map.nb_eggs_by_team(2), 6);
+        assert!(map.cells().all(|cell| cell.eggs().len() <= 1));
+
+        let mut same_seed = Map::new(Size::new(10, 10));
+        same_seed.spawn_eggs_balanced(&[0, 1, 2], 6, &mut StdRng::seed_from_u64(3));
+        let layout = |map: &Map| {
+            map.eggs()
+                .map(|egg| (egg.team_id(), egg.position()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(layout(&map), layout(&same_seed));
+    }
+
+    #[test]
+    fn test_sparse_map_only_stores_non_empty_tiles() {
+        let mut map = Map::new_sparse(Size::new(2000, 2000));
+        let mut guis = HashMap::new();
+        let pos = UPosition::new(1999, 3);
+        assert_eq!(map.stored_cells(), 0);
+        assert!(map[pos].is_empty());
+        assert!(map.get(UPosition::new(2000, 0)).is_none());
+
+        map.add_resource(Resource::Sibur, 2, pos, &mut guis);
+        let egg_id = map.spawn_egg(0, UPosition::new(0, 0));
+        assert_eq!(map.stored_cells(), 2);
+        assert_eq!(map[pos].ressources()[Resource::Sibur], 2);
+
+        map.del_resource(Resource::Sibur, 2, pos, &mut guis);
+        map.remove_egg(egg_id);
+        assert_eq!(map.stored_cells(), 0);
+
+        assert!(
+            map.del_resource(Resource::Food, 1, UPosition::new(7, 7), &mut guis)
+                .is_none()
+        );
+        assert_eq!(map.stored_cells(), 0);
+        assert_eq!(Map::new(Size::new(20, 20)).stored_cells(), 400);
+    }
+
+    #[test]
+    fn test_sparse_and_dense_maps_look_the_same() {
+        let mut dense = Map::new(Size::new(4, 3));
+        let mut sparse = Map::new_sparse(Size::new(4, 3));
+        let mut guis = HashMap::new();
+        for map in [&mut dense, &mut sparse] {
+            map.add_resource(Resource::Food, 3, UPosition::new(3, 0), &mut guis);
+            map.add_resource(Resource::Phiras, 1, UPosition::new(1, 2), &mut guis);
+            map.spawn_egg(1, UPosition::new(2, 1));
+        }
+
+        let tiles = |map: &Map| {
+            map.cells_with_positions()
+                .map(|(pos, cell)| (pos, cell.ressources().clone(), cell.eggs().len()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(tiles(&dense).len(), 12);
+        assert_eq!(tiles(&dense), tiles(&sparse));
+        assert_eq!(dense.to_string(), sparse.to_string());
+    }
+}
@@ -3,34 +3,33 @@ use crate::pending::PendingClient;
 use crate::protocol::{ClientSender, HasId, Id, ServerResponse};
 use crate::resources::{ElevationLevel, Resource, Resources};
 use crate::vec2::{HasPosition, Position, Size, UPosition};
-use rand::random;
+use rand::Rng;
+use rand::distr::{Distribution, StandardUniform};
 use tokio::sync::mpsc::Sender;
 
 #[repr(u8)]
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub enum Direction {
+    #[default]
     North,
     East,
     South,
     West,
 }
 
-impl Default for Direction {
-    fn default() -> Self {
-        Direction::new()
-    }
-}
-
-impl Direction {
-    fn new() -> Self {
-        match random::<u8>() % 4 {
+/// Lets the server draw spawn directions from its own (seedable) RNG
+impl Distribution<Direction> for StandardUniform {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Direction {
+        match rng.random_range(0..4) {
             0 => Direction::North,
             1 => Direction::East,
             2 => Direction::South,
             _ => Direction::West,
         }
     }
+}
 
+impl Direction {
     pub fn rotate_right(&mut self) {
         *self = match self {
             Direction::North => Direction::East,
@@ -80,6 +79,30 @@ impl From<Direction> for i8 {
     }
 }
 
+impl TryFrom<i8> for Direction {
+    type Error = &'static str;
+
+    fn try_from(value: i8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Direction::North),
+            2 => Ok(Direction::East),
+            3 => Ok(Direction::South),
+            4 => Ok(Direction::West),
+            _ => Err("Orientation must be between 1 and 4"),
+        }
+    }
+}
+
+impl TryFrom<u8> for Direction {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        i8::try_from(value)
+            .map_err(|_| "Orientation must be between 1 and 4")
+            .and_then(Direction::try_from)
+    }
+}
+
 #[derive(Copy, Clone, Debug, Default, PartialEq)]
 pub enum PlayerState {
     #[default]
@@ -96,8 +119,10 @@ pub struct Player {
     direction: Direction,
     elevation: ElevationLevel,
     satiety: u64,
+    max_satiety: u64,
     client_tx: Sender<ServerResponse>,
     state: PlayerState,
+    last_command_tick: u64,
 }
 
 impl Player {
@@ -120,22 +145,37 @@ impl Player {
         &mut self.state
     }
 
+    /// Tick at which the client last sent a command
+    pub fn last_command_tick(&self) -> u64 {
+        self.last_command_tick
+    }
+
+    pub fn last_command_tick_mut(&mut self) -> &mut u64 {
+        &mut self.last_command_tick
+    }
+
+    /// Lowers satiety, eating one food only when it would otherwise reach 0.
+    ///
+    /// Eating refills `REFILL_PER_FOOD`, capped at `max_satiety`. Returns the new
+    /// satiety, 0 meaning the player starved.
     pub fn reduce_satiety(&mut self, reduction: u64) -> u64 {
-        let new_satiety = self.satiety.saturating_sub(reduction);
-
-        if new_satiety == 0 {
-            if self.inventory[Resource::Food] > 0 {
-                self.inventory[Resource::Food] = self.inventory[Resource::Food].saturating_sub(1);
-                self.satiety = new_satiety.saturating_add(REFILL_PER_FOOD);
-            } else {
-                self.satiety = new_satiety;
-            }
-        } else {
-            self.satiety = new_satiety;
+        self.satiety = self.satiety.saturating_sub(reduction);
+
+        if self.satiety == 0 && self.inventory[Resource::Food] > 0 {
+            self.inventory[Resource::Food] -= 1;
+            self.satiety = REFILL_PER_FOOD.min(self.max_satiety);
         }
         self.satiety
     }
 
+    pub fn satiety(&self) -> u64 {
+        self.satiety
+    }
+
+    pub fn max_satiety(&self) -> u64 {
+        self.max_satiety
+    }
+
     pub fn team_id(&self) -> Id {
         self.team
     }
@@ -176,10 +216,10 @@ impl Player {
     }
 
     pub fn move_player(&mut self, dx: isize, dy: isize, map_size: &Size) -> &mut Self {
-        *self.position_mut().x_mut() =
-            (self.pos.x() as isize + dx).rem_euclid(map_size.x() as isize) as u64;
-        *self.position_mut().y_mut() =
-            (self.pos.y() as isize + dy).rem_euclid(map_size.y() as isize) as u64;
+        let new_pos = self
+            .pos
+            .wrapping_add(Position::new(dx as i64, dy as i64), *map_size);
+        self.position_mut().replace(new_pos);
         self
     }
 
@@ -238,8 +278,10 @@ pub struct PlayerBuilder {
     direction: Direction,
     elevation: ElevationLevel,
     satiety: u64,
+    max_satiety: u64,
     client_tx: Option<Sender<ServerResponse>>,
     state: PlayerState,
+    last_command_tick: u64,
 }
 
 impl PlayerBuilder {
@@ -252,8 +294,10 @@ impl PlayerBuilder {
             direction: Direction::default(),
             elevation: ElevationLevel::default(),
             satiety: REFILL_PER_FOOD,
+            max_satiety: REFILL_PER_FOOD,
             client_tx: None,
             state: PlayerState::default(),
+            last_command_tick: 0,
         }
     }
 
@@ -292,6 +336,12 @@ impl PlayerBuilder {
         self
     }
 
+    /// Satiety ceiling, the initial satiety is clamped to it
+    pub fn max_satiety(mut self, max_satiety: u64) -> Self {
+        self.max_satiety = max_satiety;
+        self
+    }
+
     pub fn client_tx(mut self, client_tx: Sender<ServerResponse>) -> Self {
         self.client_tx = Some(client_tx);
         self
@@ -308,6 +358,11 @@ impl PlayerBuilder {
         self
     }
 
+    pub fn last_command_tick(mut self, tick: u64) -> Self {
+        self.last_command_tick = tick;
+        self
+    }
+
     pub fn build(self) -> Result<Player, &'static str> {
         let team = self.team.ok_or("Team ID is required")?;
         let id = self.id.ok_or("Player ID is required")?;
@@ -320,9 +375,11 @@ impl PlayerBuilder {
             pos: self.pos,
             direction: self.direction,
             elevation: self.elevation,
-            satiety: self.satiety,
+            satiety: self.satiety.min(self.max_satiety),
+            max_satiety: self.max_satiety,
             client_tx,
             state: self.state,
+            last_command_tick: self.last_command_tick,
         })
     }
 }
@@ -350,6 +407,7 @@ impl Player {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::constant::SATIETY_LOSS_PER_TICK;
     use crate::resources::ElevationLevel::Level2;
     use tokio::sync::mpsc;
 
@@ -363,6 +421,7 @@ mod tests {
             .position(UPosition::new(10, 20))
             .direction(Direction::North)
             .elevation(Level2)
+            .max_satiety(200)
             .satiety(200)
             .state(PlayerState::Idle)
             .build()
@@ -387,4 +446,131 @@ mod tests {
         direction.rotate_left();
         assert_eq!(direction, Direction::West);
     }
+
+    #[tokio::test]
+    async fn test_reduce_satiety() {
+        let (tx, _rx) = mpsc::channel(10);
+        let mut player = PlayerBuilder::new()
+            .team(0)
+            .id(1)
+            .client_tx(tx)
+            .build()
+            .unwrap();
+
+        let satiety = player.satiety();
+        assert_eq!(
+            player.reduce_satiety(SATIETY_LOSS_PER_TICK),
+            satiety - SATIETY_LOSS_PER_TICK
+        );
+        assert_eq!(player.satiety(), satiety - SATIETY_LOSS_PER_TICK);
+        assert_eq!(player.inventory()[Resource::Food], 10);
+    }
+
+    #[tokio::test]
+    async fn test_reduce_satiety_eats_food() {
+        let (tx, _rx) = mpsc::channel(10);
+        let mut player = PlayerBuilder::new()
+            .team(0)
+            .id(1)
+            .client_tx(tx)
+            .satiety(SATIETY_LOSS_PER_TICK)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            player.reduce_satiety(SATIETY_LOSS_PER_TICK),
+            REFILL_PER_FOOD
+        );
+        assert_eq!(player.satiety(), player.max_satiety());
+        assert_eq!(player.inventory()[Resource::Food], 9);
+    }
+
+    #[tokio::test]
+    async fn test_reduce_satiety_starves() {
+        let (tx, _rx) = mpsc::channel(10);
+        let mut player = PlayerBuilder::new()
+            .team(0)
+            .id(1)
+            .client_tx(tx)
+            .inventory(Resources::default())
+            .satiety(SATIETY_LOSS_PER_TICK)
+            .build()
+            .unwrap();
+
+        assert_eq!(player.reduce_satiety(SATIETY_LOSS_PER_TICK), 0);
+    }
+
+    #[tokio::test]
+    async fn test_reduce_satiety_keeps_food_above_threshold() {
+        let (tx, _rx) = mpsc::channel(10);
+        let mut player = PlayerBuilder::new()
+            .team(0)
+            .id(1)
+            .client_tx(tx)
+            .satiety(SATIETY_LOSS_PER_TICK + 1)
+            .build()
+            .unwrap();
+
+        assert_eq!(player.reduce_satiety(SATIETY_LOSS_PER_TICK), 1);
+        assert_eq!(player.inventory()[Resource::Food], 10);
+    }
+
+    #[tokio::test]
+    async fn test_refill_is_capped() {
+        let (tx, _rx) = mpsc::channel(10);
+        let mut player = PlayerBuilder::new()
+            .team(0)
+            .id(1)
+            .client_tx(tx)
+            .max_satiety(50)
+            .satiety(REFILL_PER_FOOD)
+            .build()
+            .unwrap();
+        assert_eq!(player.satiety(), 50);
+
+        for _ in 0..10 {
+            player.reduce_satiety(50);
+            assert_eq!(player.satiety(), 50);
+        }
+        assert_eq!(player.inventory()[Resource::Food], 0);
+        assert_eq!(
+            player.reduce_satiety(SATIETY_LOSS_PER_TICK),
+            50 - SATIETY_LOSS_PER_TICK
+        );
+    }
+
+    #[test]
+    fn test_direction_wire_round_trip() {
+        for direction in [
+            Direction::North,
+            Direction::East,
+            Direction::South,
+            Direction::West,
+        ] {
+            let wire = i8::from(direction.clone());
+            assert_eq!(Direction::try_from(wire), Ok(direction.clone()));
+            assert_eq!(Direction::try_from(wire as u8), Ok(direction));
+        }
+    }
+
+    #[test]
+    fn test_direction_invalid_wire_value() {
+        assert!(Direction::try_from(0i8).is_err());
+        assert!(Direction::try_from(5i8).is_err());
+        assert!(Direction::try_from(-1i8).is_err());
+        assert!(Direction::try_from(200u8).is_err());
+    }
+
+    #[test]
+    fn test_random_direction_is_seeded() {
+        use rand::SeedableRng;
+        use rand::rngs::StdRng;
+
+        let draw = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..16).map(|_| rng.random()).collect::<Vec<Direction>>()
+        };
+
+        assert_eq!(draw(42), draw(42));
+    }
 }
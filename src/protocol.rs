@@ -44,6 +44,29 @@ pub enum GUIAction {
     Pin(Id),
     Sgt,
     Sst(u64),
+    Dbg(DebugTopic),
+    Subscribe(GuiCategory, bool),
+    Pls,
+    Kill(Id),
+    Setr(UPosition, Vec<(Resource, u64)>),
+    Tp(Id, UPosition),
+    Egl,
+}
+
+/// Topics of the non-spec `dbg` GUI command
+#[derive(Debug)]
+pub enum DebugTopic {
+    Scheduler,
+    Map,
+}
+
+/// Kinds of unsolicited updates a gui can opt out of with `unsub`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GuiCategory {
+    Positions,
+    Inventories,
+    Resources,
+    Incantations,
 }
 
 #[derive(Debug)]
@@ -52,7 +75,7 @@ pub enum PendingAction {
     Login(String),
 }
 
-pub(crate) type LookResult = Vec<(u64, Resources)>; // u64 = how many players on this cell
+pub(crate) type LookResult = Vec<(u64, u64, Resources)>; // how many players and eggs on this cell
 
 #[derive(Debug)]
 pub enum AIResponse {
@@ -65,6 +88,7 @@ pub enum AIResponse {
     ConnectNbr(u64),
     Eject(u8),
     Look(LookResult),
+    Time(u64, u64),
 }
 
 pub type BctResponse = (UPosition, Resources);
@@ -79,6 +103,7 @@ pub enum GUIResponse {
     Mct(Vec<BctResponse>),
     Tna(Vec<String>),
     Pnw(Id, UPosition, Direction, ElevationLevel, String),
+    /// Absolute position, a jump of more than one tile means the player wrapped around the map
     Ppo(Id, UPosition, Direction),
     Plv(Id, ElevationLevel),
     Pin(Id, UPosition, Resources),
@@ -90,13 +115,32 @@ pub enum GUIResponse {
     Pdr(Id, Resource),
     Pgt(Id, Resource),
     Pdi(Id),
-    Enw(Id, Id, UPosition),
+    Enw(Id, Option<Id>, UPosition), // no layer for the eggs laid at start
     Ebo(Id),
     Edi(Id),
     Sgt(u64),
     Sst(u64),
     Seg(String),
     Smg(Arc<String>),
+    Dbg(Vec<String>),
+    Subscribe(GuiCategory, bool),
+}
+
+impl GUIResponse {
+    /// Category used to filter the update, `None` when it is always sent
+    pub fn category(&self) -> Option<GuiCategory> {
+        match self {
+            GUIResponse::Ppo(..) => Some(GuiCategory::Positions),
+            GUIResponse::Pin(..) | GUIResponse::Pgt(..) | GUIResponse::Pdr(..) => {
+                Some(GuiCategory::Inventories)
+            }
+            GUIResponse::Bct(..) | GUIResponse::Mct(..) => Some(GuiCategory::Resources),
+            GUIResponse::Pic(..) | GUIResponse::Pie(..) | GUIResponse::Plv(..) => {
+                Some(GuiCategory::Incantations)
+            }
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -18,9 +18,25 @@ pub enum Resource {
 }
 
 impl Resource {
+    /// Order in which resources are written on the wire
+    pub const WIRE_ORDER: [Resource; 7] =
+        [Food, Linemate, Deraumere, Sibur, Mendiane, Phiras, Thystame];
+
     pub fn iter() -> impl Iterator<Item = Resource> {
         [Deraumere, Linemate, Mendiane, Phiras, Sibur, Thystame, Food].into_iter()
     }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Deraumere => "deraumere",
+            Linemate => "linemate",
+            Mendiane => "mendiane",
+            Phiras => "phiras",
+            Sibur => "sibur",
+            Thystame => "thystame",
+            Food => "food",
+        }
+    }
 }
 
 #[repr(u8)]
@@ -162,8 +178,10 @@ pub struct Resources {
 }
 
 impl Resources {
-    pub fn new() -> Self {
-        Self::default()
+    pub const fn new() -> Self {
+        Self {
+            contents: [0; Resource::Food as usize + 1],
+        }
     }
 
     pub fn builder() -> ResourcesBuilder {
@@ -176,6 +194,22 @@ impl Resources {
             .zip(required.contents.iter())
             .all(|(available, needed)| available >= needed)
     }
+
+    /// Sum of all the amounts
+    pub fn total(&self) -> u64 {
+        self.contents.iter().sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.contents.iter().all(|amount| *amount == 0)
+    }
+
+    /// Named amounts, in wire order
+    pub fn iter_named(&self) -> impl Iterator<Item = (&'static str, u64)> {
+        Resource::WIRE_ORDER
+            .into_iter()
+            .map(|resource| (resource.name(), self[resource]))
+    }
 }
 
 impl Index<Resource> for Resources {
@@ -192,6 +226,68 @@ impl IndexMut<Resource> for Resources {
     }
 }
 
+/// Share of tiles that should hold each resource, several resources can share a tile
+/// so the densities are independent and do not have to sum to 1 (1.28 by default)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResourceDensity {
+    pub food: f64,
+    pub linemate: f64,
+    pub deraumere: f64,
+    pub sibur: f64,
+    pub mendiane: f64,
+    pub phiras: f64,
+    pub thystame: f64,
+}
+
+impl Default for ResourceDensity {
+    fn default() -> Self {
+        Self {
+            food: 0.5,
+            linemate: 0.3,
+            deraumere: 0.15,
+            sibur: 0.1,
+            mendiane: 0.1,
+            phiras: 0.08,
+            thystame: 0.05,
+        }
+    }
+}
+
+impl ResourceDensity {
+    pub fn get(&self, resource: Resource) -> f64 {
+        match resource {
+            Deraumere => self.deraumere,
+            Linemate => self.linemate,
+            Mendiane => self.mendiane,
+            Phiras => self.phiras,
+            Sibur => self.sibur,
+            Thystame => self.thystame,
+            Food => self.food,
+        }
+    }
+
+    /// Returns the first resource whose density is outside of [0, 1]
+    pub fn validate(&self) -> Result<(), (Resource, f64)> {
+        match Resource::WIRE_ORDER
+            .into_iter()
+            .find(|resource| !(0.0..=1.0).contains(&self.get(*resource)))
+        {
+            Some(resource) => Err((resource, self.get(resource))),
+            None => Ok(()),
+        }
+    }
+
+    /// Amount of each resource the map should hold
+    pub fn targets(&self, nb_tiles: u64) -> Resources {
+        Resource::iter()
+            .fold(Resources::builder(), |builder, resource| {
+                let target = (self.get(resource) * nb_tiles as f64) as u64;
+                builder.resource(resource, target)
+            })
+            .build()
+    }
+}
+
 pub struct ResourcesBuilder {
     resources: Resources,
 }
@@ -265,4 +361,74 @@ mod tests {
         assert_eq!(resources[Food], 10);
         assert_eq!(resources[Mendiane], 0);
     }
+
+    #[test]
+    fn test_default_density_targets() {
+        let targets = ResourceDensity::default().targets(100);
+
+        for resource in Resource::iter() {
+            let expected = match resource {
+                Food => 50,
+                Linemate => 30,
+                Deraumere => 15,
+                Sibur => 10,
+                Mendiane => 10,
+                Phiras => 8,
+                Thystame => 5,
+            };
+            assert_eq!(targets[resource], expected, "{:?}", resource);
+        }
+    }
+
+    #[test]
+    fn test_density_validation() {
+        assert!(ResourceDensity::default().validate().is_ok());
+
+        let density = ResourceDensity {
+            phiras: 1.5,
+            ..Default::default()
+        };
+        assert_eq!(density.validate(), Err((Phiras, 1.5)));
+
+        let density = ResourceDensity {
+            food: -0.1,
+            sibur: f64::NAN,
+            ..Default::default()
+        };
+        assert_eq!(density.validate(), Err((Food, -0.1)));
+    }
+
+    #[test]
+    fn test_iter_named_is_in_wire_order() {
+        let resources = Resources::builder().food(1).thystame(7).build();
+        let named: Vec<(&str, u64)> = resources.iter_named().collect();
+
+        assert_eq!(
+            named,
+            vec![
+                ("food", 1),
+                ("linemate", 0),
+                ("deraumere", 0),
+                ("sibur", 0),
+                ("mendiane", 0),
+                ("phiras", 0),
+                ("thystame", 7),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_total_and_is_empty() {
+        let empty = Resources::new();
+        assert_eq!(empty.total(), 0);
+        assert!(empty.is_empty());
+
+        let single = Resources::builder().thystame(1).build();
+        assert_eq!(single.total(), 1);
+        assert!(!single.is_empty());
+
+        let mixed = Resources::builder().food(10).linemate(3).sibur(2).build();
+        assert_eq!(mixed.total(), 15);
+        assert!(!mixed.is_empty());
+    }
 }
@@ -1,43 +1,58 @@
-use crate::connection::Connection;
-use crate::constant::{RELATIVE_DIRECTIONS, SATIETY_LOSS_PER_TICK};
-use crate::event::Event;
-use crate::event::EventScheduler;
-use crate::gui::{Gui, GuiBuilder};
-use crate::map::Map;
-use crate::pending::PendingClient;
-use crate::player::{Direction, Player, PlayerState};
-use crate::protocol::PendingResponse::{LogAs, Shared};
-use crate::protocol::{
-    AIAction, AIResponse, BctResponse, ClientSender, EventType, GUIAction, GUIResponse, GameEvent,
-    HasId, Id, PendingAction, ServerResponse, SharedAction, SharedResponse, TeamType,
+use crate::connection::{Connection, ConnectionError, ConnectionSlot};
+use crate::constant::{
+    DEFAULT_EVENT_BATCH_SIZE, DEFAULT_FORK_COST, DEFAULT_IDLE_TIMEOUT, DEFAULT_MAX_FREQ,
+    DEFAULT_MAX_MAP_CELLS, DEFAULT_MAX_QUEUED_COMMANDS, REFILL_PER_FOOD, SHUTDOWN_GRACE_PERIOD,
 };
-use crate::resources::{Resource, Resources, LEVEL_REQUIREMENTS};
-use crate::sound::get_sound_direction;
-use crate::team::Team;
-use crate::vec2::{HasPosition, Position, Size, UPosition};
+use crate::game::{EndAction, Game};
+use crate::map::EggPlacement;
+use crate::pending::PendingClient;
+use crate::protocol::{EventType, Id, ServerResponse};
+use crate::resources::{Resource, ResourceDensity};
 use log::{debug, info, warn};
-use rand::Rng;
-use std::collections::HashMap;
 use std::error::Error;
+use std::io::ErrorKind;
 use std::net::SocketAddr;
-use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use thiserror::Error;
-use tokio::net::{TcpListener, TcpStream};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpSocket, TcpStream, lookup_host};
 use tokio::sync::mpsc;
-use tokio::time::Instant;
+use tokio::task::JoinSet;
 use tokio::{select, time};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ServerConfig {
-    addr: String,
-    port: u16,
-    width: u8,
-    height: u8,
-    teams: Vec<String>,
-    clients_nb: u64,
-    freq: u16,
+    pub(crate) addr: String,
+    pub(crate) port: u16,
+    pub(crate) width: u8,
+    pub(crate) height: u8,
+    pub(crate) teams: Vec<String>,
+    pub(crate) clients_nb: u64,
+    pub(crate) freq: u16,
+    pub(crate) debug_commands: bool,
+    pub(crate) time_command: bool,
+    pub(crate) look_eggs: bool,
+    pub(crate) roster_command: bool,
+    pub(crate) admin_commands: bool,
+    pub(crate) nodelay: bool,
+    pub(crate) socket_buffer_size: Option<u32>,
+    pub(crate) max_connections: usize,
+    pub(crate) idle_timeout: Option<u64>,
+    pub(crate) max_satiety: u64,
+    pub(crate) seed: Option<u64>,
+    pub(crate) resource_density: ResourceDensity,
+    pub(crate) egg_placement: EggPlacement,
+    pub(crate) banner: Option<String>,
+    pub(crate) trace_clients: bool,
+    pub(crate) event_batch_size: usize,
+    pub(crate) max_queued_commands: u64,
+    pub(crate) fork_cost: u64,
+    pub(crate) egg_lifetime: Option<u64>,
+    pub(crate) end_action: EndAction,
+    pub(crate) max_freq: u64,
+    pub(crate) max_map_cells: u64,
+    pub(crate) sparse_map: bool,
 }
 
 impl ServerConfig {
@@ -58,8 +73,210 @@ impl ServerConfig {
             teams,
             clients_nb,
             freq,
+            debug_commands: false,
+            time_command: false,
+            look_eggs: false,
+            roster_command: false,
+            admin_commands: false,
+            nodelay: true,
+            socket_buffer_size: None,
+            max_connections: 1024,
+            idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+            max_satiety: REFILL_PER_FOOD,
+            seed: None,
+            resource_density: ResourceDensity::default(),
+            egg_placement: EggPlacement::default(),
+            banner: None,
+            trace_clients: false,
+            event_batch_size: DEFAULT_EVENT_BATCH_SIZE,
+            max_queued_commands: DEFAULT_MAX_QUEUED_COMMANDS,
+            fork_cost: DEFAULT_FORK_COST,
+            egg_lifetime: None,
+            end_action: EndAction::default(),
+            max_freq: DEFAULT_MAX_FREQ,
+            max_map_cells: DEFAULT_MAX_MAP_CELLS,
+            sparse_map: false,
+        }
+    }
+
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn width(&self) -> u8 {
+        self.width
+    }
+
+    pub fn height(&self) -> u8 {
+        self.height
+    }
+
+    pub fn teams(&self) -> &[String] {
+        &self.teams
+    }
+
+    pub fn clients_nb(&self) -> u64 {
+        self.clients_nb
+    }
+
+    pub fn freq(&self) -> u16 {
+        self.freq
+    }
+
+    /// Greeting sent on connect, `WELCOME` then the optional banner line
+    pub fn welcome_message(&self) -> String {
+        match &self.banner {
+            Some(banner) => format!("WELCOME\n{}\n", banner),
+            None => "WELCOME\n".to_string(),
         }
     }
+
+    /// Non-spec line sent after `WELCOME`, e.g. a server name or protocol version.
+    /// Clients must skip it, so keep it off for strict ones
+    pub fn banner(mut self, banner: String) -> Self {
+        self.banner = Some(banner.replace("\n", "_").replace("\r", "_"));
+        self
+    }
+
+    /// Logs every line received from and sent to each client, off by default
+    pub fn trace_clients(mut self, enabled: bool) -> Self {
+        self.trace_clients = enabled;
+        self
+    }
+
+    /// Enables the non-spec `dbg` GUI commands
+    pub fn debug_commands(mut self, enabled: bool) -> Self {
+        self.debug_commands = enabled;
+        self
+    }
+
+    /// Enables the non-spec `Time` AI command, answered with the current tick and frequency
+    pub fn time_command(mut self, enabled: bool) -> Self {
+        self.time_command = enabled;
+        self
+    }
+
+    /// Enables the non-spec GUI admin commands: `kill #id`, `setr x y resource amount..`
+    /// and `tp #id x y`
+    pub fn admin_commands(mut self, enabled: bool) -> Self {
+        self.admin_commands = enabled;
+        self
+    }
+
+    /// Non-spec, `Look` lists an `egg` per egg of a tile, after its players
+    pub fn look_eggs(mut self, enabled: bool) -> Self {
+        self.look_eggs = enabled;
+        self
+    }
+
+    /// Enables the non-spec `pls` and `egl` GUI commands, answered with a `pnw`
+    /// per live player and an `enw` per live egg
+    pub fn roster_command(mut self, enabled: bool) -> Self {
+        self.roster_command = enabled;
+        self
+    }
+
+    /// Disables Nagle's algorithm on client sockets (enabled by default)
+    pub fn nodelay(mut self, enabled: bool) -> Self {
+        self.nodelay = enabled;
+        self
+    }
+
+    /// Kernel send/receive buffer size hint, inherited by accepted sockets
+    pub fn socket_buffer_size(mut self, size: u32) -> Self {
+        self.socket_buffer_size = Some(size);
+        self
+    }
+
+    /// Maximum number of simultaneously open client connections
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = max;
+        self
+    }
+
+    /// Ticks an AI may stay silent before being disconnected, `None` to never disconnect
+    pub fn idle_timeout(mut self, ticks: Option<u64>) -> Self {
+        self.idle_timeout = ticks;
+        self
+    }
+
+    /// Highest satiety a player can reach by eating
+    pub fn max_satiety(mut self, max_satiety: u64) -> Self {
+        self.max_satiety = max_satiety;
+        self
+    }
+
+    /// Seeds the server RNG so games can be reproduced
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Resource scarcity, each density must be in [0, 1]
+    pub fn resource_density(mut self, density: ResourceDensity) -> Self {
+        self.resource_density = density;
+        self
+    }
+
+    /// Commands an AI may have queued at once, the extra ones are dropped
+    pub fn max_queued_commands(mut self, max: u64) -> Self {
+        self.max_queued_commands = max;
+        self
+    }
+
+    /// Maximum client events processed in a row before the next tick can run, at least 1
+    pub fn event_batch_size(mut self, size: usize) -> Self {
+        self.event_batch_size = size.max(1);
+        self
+    }
+
+    /// Ticks a `Fork` takes, 42 by default
+    pub fn fork_cost(mut self, ticks: u64) -> Self {
+        self.fork_cost = ticks;
+        self
+    }
+
+    /// Ticks after which a forked egg nobody hatched dies, `None` (the default) to keep them
+    pub fn egg_lifetime(mut self, ticks: Option<u64>) -> Self {
+        self.egg_lifetime = ticks;
+        self
+    }
+
+    /// Highest tick frequency, the configured one and `sst` requests are clamped to [1, max]
+    pub fn max_freq(mut self, max_freq: u64) -> Self {
+        self.max_freq = max_freq.max(1);
+        self
+    }
+
+    /// Largest width * height accepted, bigger maps are refused before allocating.
+    ///
+    /// Only a cap below 255 * 255 refuses anything, the `u8` dimensions already bound the map
+    pub fn max_map_cells(mut self, max_cells: u64) -> Self {
+        self.max_map_cells = max_cells;
+        self
+    }
+
+    /// Only keeps the non-empty tiles in memory, for big maps with few resources
+    pub fn sparse_map(mut self, sparse: bool) -> Self {
+        self.sparse_map = sparse;
+        self
+    }
+
+    /// What happens once a team wins, the game freezes by default
+    pub fn end_action(mut self, action: EndAction) -> Self {
+        self.end_action = action;
+        self
+    }
+
+    /// How the initial eggs are spread over the map, random by default
+    pub fn egg_placement(mut self, placement: EggPlacement) -> Self {
+        self.egg_placement = placement;
+        self
+    }
 }
 
 pub struct ThreadChannel<T> {
@@ -71,109 +288,82 @@ pub struct Server {
     global_channel: ThreadChannel<EventType>,
     tick_interval: time::Interval,
     socket: TcpListener,
-    map: Map,
-    teams: HashMap<Id, Team>,
-    pending_clients: HashMap<Id, PendingClient>,
-    clients: HashMap<Id, Player>,
-    guis: HashMap<Id, Gui>,
-    event_scheduler: EventScheduler<Event>,
-    last_gui_notify: Instant,
+    config: ServerConfig,
+    game: Game,
+    nodelay: bool,
+    connections: Arc<AtomicUsize>,
+    max_connections: usize,
+    connection_tasks: JoinSet<Result<(), ConnectionError>>,
 }
 
 #[derive(Debug, Error)]
 pub enum ServerError {
     #[error("socket error: {0}")]
     FailedToBind(#[from] std::io::Error),
+    #[error("{0:?} density {1} is outside of [0, 1]")]
+    InvalidDensity(Resource, f64),
+    #[error("map size {0}x{1} is empty or has more than {2} cells")]
+    InvalidMapSize(u64, u64, u64),
+}
+
+/// Ticks `freq` times per second.
+///
+/// When a tick runs longer than the period the missed ticks are skipped rather
+/// than bursted, so a momentary overload slows the game down instead of
+/// piling up catch-up ticks that would overload it further.
+fn tick_interval(freq: u64) -> time::Interval {
+    let period = (1_000_000_000f64 / freq as f64) as u64;
+    let mut interval = time::interval(time::Duration::from_nanos(period));
+    interval.set_missed_tick_behavior(time::MissedTickBehavior::Skip);
+    interval
 }
 
 impl Server {
     pub async fn from_config(config: ServerConfig) -> Result<Server, ServerError> {
         let addr = format!("{}:{}", config.addr, config.port);
         debug!("Server using config {:?}", config);
-        let socket = TcpListener::bind(&addr).await?;
+        let game = Game::from_config(&config)?;
+        let socket = Self::bind(&addr, config.socket_buffer_size).await?;
         let (tx, rx) = mpsc::channel::<EventType>(32);
-        let tick_interval = time::interval(time::Duration::from_nanos(
-            (1_000_000_000f64 / config.freq as f64) as u64,
-        ));
-
-        let mut teams: HashMap<Id, Team> = HashMap::new();
-
-        for (team_id, team_name) in config.teams.into_iter().enumerate() {
-            if team_name == "GRAPHIC" {
-                warn!("'GRAPHIC' can't be used as a team name and will be ignored");
-                continue;
-            }
-            teams.insert(
-                team_id as Id,
-                Team::new(
-                    team_id as Id,
-                    team_name
-                        .replace("\n", "_")
-                        .replace("\r", "_")
-                ),
-            );
-        }
-
-        let mut map = Map::new(Size::new(config.width as u64, config.height as u64));
-
-        for (team_id, ..) in &teams {
-            map.spawn_eggs(*team_id, config.clients_nb);
-        }
+        let tick_interval = tick_interval(game.frequency());
 
         Ok(Server {
             global_channel: ThreadChannel { tx, rx },
             tick_interval,
             socket,
-            map,
-            teams,
-            pending_clients: HashMap::new(),
-            clients: HashMap::new(),
-            guis: HashMap::new(),
-            event_scheduler: EventScheduler::new(),
-            last_gui_notify: Instant::now(),
+            game,
+            nodelay: config.nodelay,
+            connections: Arc::new(AtomicUsize::new(0)),
+            max_connections: config.max_connections,
+            connection_tasks: JoinSet::new(),
+            config,
         })
     }
 
-    // resource density
-    // food 0.5
-    // linemate 0.3
-    // deraumere 0.15
-    // sibur 0.1
-    // mendiane 0.1
-    // phiras 0.08
-    // thystame 0.05
-    fn spawn_resources(&mut self) {
-        let size_x = self.map.size().x();
-        let size_y = self.map.size().y();
-
-        let total: u64 = size_x * size_y;
-        let resources: [(Resource, u64); 7] = [
-            (Resource::Food, (0.5 * total as f64) as u64),
-            (Resource::Linemate, (0.3 * total as f64) as u64),
-            (Resource::Deraumere, (0.15 * total as f64) as u64),
-            (Resource::Sibur, (0.1 * total as f64) as u64),
-            (Resource::Mendiane, (0.1 * total as f64) as u64),
-            (Resource::Phiras, (0.08 * total as f64) as u64),
-            (Resource::Thystame, (0.05 * total as f64) as u64),
-        ];
-
-        for res in Resource::iter() {
-            if self.map.resources()[res] >= resources[res as usize].1 {
-                continue;
-            }
-            let nb_missing = resources[res as usize].1 - self.map.resources()[res];
-            (0..nb_missing).for_each(|_| {
-                let x = rand::rng().random_range(0..size_x);
-                let y = rand::rng().random_range(0..size_y);
-                let pos = UPosition::new(x, y);
-                self.map.add_resource(res, 1, pos, &mut self.guis);
-            });
+    async fn bind(addr: &str, buffer_size: Option<u32>) -> Result<TcpListener, ServerError> {
+        let addr = lookup_host(addr).await?.next().ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::AddrNotAvailable,
+                format!("can't resolve {}", addr),
+            )
+        })?;
+        let socket = if addr.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            TcpSocket::new_v6()?
+        };
+        socket.set_reuseaddr(true)?;
+        if let Some(size) = buffer_size {
+            socket.set_recv_buffer_size(size)?;
+            socket.set_send_buffer_size(size)?;
         }
+        socket.bind(addr)?;
+        Ok(socket.listen(1024)?)
     }
 
-    fn set_tick_interval(&mut self, freq: u16) {
-        let freq = (1_000_000_000f64 / freq as f64) as u64;
-        self.tick_interval = time::interval(time::Duration::from_nanos(freq));
+    /// Called again on `sst`, the new interval keeps the same overload behavior
+    fn set_tick_interval(&mut self, freq: u64) {
+        self.tick_interval = tick_interval(freq);
     }
 
     pub async fn run(&mut self) -> Result<(), Box<dyn Error>> {
@@ -185,783 +375,380 @@ impl Server {
                     self.accept_client(socket, addr);
                 },
 
-                instant = self.tick_interval.tick() => {
-                    self.update(instant).await;
+                _ = self.tick_interval.tick() => {
+                    self.game.tick_once().await;
+                    if self.game.is_stopped() {
+                        info!("Game over, stopping the server");
+                        self.shutdown().await;
+                        return Ok(());
+                    }
                 },
 
+                Some(_) = self.connection_tasks.join_next(), if !self.connection_tasks.is_empty() => {},
+
                 Some(res) = self.global_channel.rx.recv() => {
                     self.process_events(res).await;
+                    self.process_ready_events().await;
                 },
             }
         }
     }
 
-    fn accept_client(&mut self, socket: TcpStream, _: SocketAddr) {
+    /// Drops every client then lets the connections flush what was queued for them,
+    /// e.g. the `dead` and `seg` of the end of the game
+    async fn shutdown(&mut self) {
+        self.game.disconnect_all();
+        // Connections reporting their disconnection must not wait on a loop that has stopped
+        self.global_channel.rx.close();
+        let drained = time::timeout(SHUTDOWN_GRACE_PERIOD, async {
+            while self.connection_tasks.join_next().await.is_some() {}
+        })
+        .await;
+        if drained.is_err() {
+            warn!(
+                "{} connections still open after {:?}, closing them",
+                self.connection_tasks.len(),
+                SHUTDOWN_GRACE_PERIOD
+            );
+            self.connection_tasks.shutdown().await;
+        }
+    }
+
+    /// Configuration the server was started with, `sst` changes are not reflected
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    fn accept_client(&mut self, mut socket: TcpStream, addr: SocketAddr) {
+        let Some(slot) = ConnectionSlot::try_acquire(&self.connections, self.max_connections)
+        else {
+            warn!(
+                "Refused connection from {:?}: {} connections already open",
+                addr, self.max_connections
+            );
+            tokio::spawn(async move {
+                let _ = socket.write_all(b"ko\n").await;
+                let _ = socket.shutdown().await;
+            });
+            return;
+        };
         static CLIENT_ID: AtomicU64 = AtomicU64::new(0);
         let client_id: Id = CLIENT_ID.fetch_add(1, Ordering::Relaxed);
-        info!(
-            "Accepted connection from {:?} with id {}",
-            socket.peer_addr().unwrap(),
-            client_id
-        );
+        info!("Accepted connection from {:?} with id {}", addr, client_id);
+        if self.nodelay {
+            if let Err(e) = socket.set_nodelay(true) {
+                warn!("Client {}: failed to set TCP_NODELAY: {}", client_id, e);
+            }
+        }
         let server_tx = self.global_channel.tx.clone();
+        let welcome = self.config.welcome_message();
+        let trace = self.config.trace_clients;
+        let max_queued_commands = self.config.max_queued_commands;
         let (client_tx, client_rx) = mpsc::channel::<ServerResponse>(8196);
-        self.pending_clients.insert(
+        self.game.add_pending_client(PendingClient {
             client_id,
-            PendingClient {
-                client_id,
-                client_tx,
-            },
-        );
-        tokio::spawn(async move {
-            let (mut client, read_half) = Connection::new(client_id, socket, server_tx).await;
+            client_tx,
+        });
+        self.connection_tasks.spawn(async move {
+            let _slot = slot;
+            let (mut client, read_half) =
+                Connection::new(client_id, socket, server_tx, welcome.as_bytes()).await;
+            client.set_trace(trace);
+            client.set_max_queued_commands(max_queued_commands);
             client.handle(client_rx, read_half).await
         });
     }
 
-    async fn update(&mut self, _instant: time::Instant) {
-        //info!("Updating current tick {:?}", self.event_scheduler.current_tick());
-        //info!("Updating server {}", self.clients.len());
-        //print!("\x1B[2J\x1B[1;1H"); // Effacer l'écran et replacer le curseur en haut à gauche
-        //println!("{}", self.map);
-        //println!("{:?}", self.clients);
-        //self.event_scheduler.display_pending_events();
-        self.spawn_resources();
-        let expired_events = self.event_scheduler.tick();
-        for timed_event in expired_events {
-            // do or ignore event if dead
-            match timed_event.data {
-                Event::Broadcast(str) => {
-                    let Some(emitter) = self.clients.get(&timed_event.player_id) else {
-                        continue;
-                    };
-                    let str = Arc::new(str);
-                    for receiver in self
-                        .clients
-                        .values()
-                        .filter(|receiver| receiver.id() != emitter.id())
-                    {
-                        let dir =
-                            get_sound_direction(emitter.into(), receiver.into(), self.map.size());
-                        let _ = receiver.send_to_client(ServerResponse::AI(AIResponse::Broadcast(
-                            dir,
-                            str.clone(),
-                        )));
-                    }
-                    //gui
-                    for (.., gui) in &self.guis {
-                        gui.send_to_client(ServerResponse::Gui(GUIResponse::Pbc(
-                            emitter.id(),
-                            str.clone(),
-                        )));
-                    }
+    /// Handles the events already queued after the first one, in order, up to the batch size
+    async fn process_ready_events(&mut self) {
+        for _ in 1..self.config.event_batch_size {
+            match self.global_channel.rx.try_recv() {
+                Ok(event) => self.process_events(event).await,
+                Err(_) => break,
+            }
+        }
+    }
 
-                    emitter
-                        .send_to_client(ServerResponse::AI(AIResponse::Shared(SharedResponse::Ok)));
-                }
-                Event::Forward => {
-                    let Some(emitter) = self.clients.get_mut(&timed_event.player_id) else {
-                        continue;
-                    };
-                    emitter
-                        .move_forward(&self.map.size())
-                        .send_to_client(ServerResponse::AI(AIResponse::Shared(SharedResponse::Ok)));
-                    //gui
-                    for (.., gui) in &self.guis {
-                        gui.send_to_client(ServerResponse::Gui(GUIResponse::Ppo(
-                            emitter.id(),
-                            emitter.position(),
-                            emitter.direction(),
-                        )));
-                    }
-                }
-                Event::Right => {
-                    let Some(emitter) = self.clients.get_mut(&timed_event.player_id) else {
-                        continue;
-                    };
-                    emitter.direction_mut().rotate_right();
-                    emitter
-                        .send_to_client(ServerResponse::AI(AIResponse::Shared(SharedResponse::Ok)));
-                    //gui
-                    for (.., gui) in &self.guis {
-                        gui.send_to_client(ServerResponse::Gui(GUIResponse::Ppo(
-                            emitter.id(),
-                            emitter.position(),
-                            emitter.direction(),
-                        )));
-                    }
-                }
-                Event::Left => {
-                    let Some(emitter) = self.clients.get_mut(&timed_event.player_id) else {
-                        continue;
-                    };
-                    emitter.direction_mut().rotate_left();
-                    emitter
-                        .send_to_client(ServerResponse::AI(AIResponse::Shared(SharedResponse::Ok)));
-
-                    //gui
-                    for (.., gui) in &self.guis {
-                        gui.send_to_client(ServerResponse::Gui(GUIResponse::Ppo(
-                            emitter.id(),
-                            emitter.position(),
-                            emitter.direction(),
-                        )));
-                    }
-                }
-                Event::Look => {
-                    let Some(emitter) = self.clients.get_mut(&timed_event.player_id) else {
-                        continue;
-                    };
-                    let visible_pos = emitter.get_visible_positions();
-                    let mut res = vec![];
-                    for cell_pos in visible_pos {
-                        let converted_pos = self.map.get_pos_signed(cell_pos);
-                        let nb_players_on_cell = self
-                            .clients
-                            .values()
-                            .filter(|client| client.position() == converted_pos)
-                            .count();
-                        let resources_on_cell =
-                            self.map.get_ressources_at_pos(converted_pos).clone();
-                        res.push((nb_players_on_cell as u64, resources_on_cell));
-                    }
-                    self.clients
-                        .get_mut(&timed_event.player_id)
-                        .unwrap()
-                        .send_to_client(ServerResponse::AI(AIResponse::Look(res)));
-                }
-                Event::Inventory => {
-                    let Some(emitter) = self.clients.get_mut(&timed_event.player_id) else {
-                        continue;
-                    };
-                    emitter.send_to_client(ServerResponse::AI(AIResponse::Inventory(
-                        emitter.inventory(),
-                    )));
-                }
-                Event::ConnectNbr => {
-                    let Some(emitter) = self.clients.get_mut(&timed_event.player_id) else {
-                        continue;
-                    };
-                    emitter.send_to_client(ServerResponse::AI(AIResponse::ConnectNbr(
-                        self.map.nb_eggs_by_team(emitter.team_id()),
-                    )));
-                }
-                Event::Fork => {
-                    let Some(emitter) = self.clients.get_mut(&timed_event.player_id) else {
-                        continue;
-                    };
-                    let egg_id = self.map.spawn_egg(emitter.team_id(), emitter.position());
-                    //todo egg hatching ? 600 ticks ?
-
-                    //gui
-                    for (.., gui) in &self.guis {
-                        gui.send_to_client(ServerResponse::Gui(GUIResponse::Pfk(emitter.id())));
-                        gui.send_to_client(ServerResponse::Gui(GUIResponse::Enw(
-                            egg_id,
-                            emitter.id(),
-                            emitter.position(),
-                        )));
-                    }
+    async fn process_events(&mut self, event: EventType) {
+        let frequency = self.game.frequency();
+        self.game.process_events(event).await;
+        if self.game.frequency() != frequency {
+            self.set_tick_interval(self.game.frequency());
+        }
+    }
+}
 
-                    emitter
-                        .send_to_client(ServerResponse::AI(AIResponse::Shared(SharedResponse::Ok)));
-                }
-                Event::Eject => {
-                    let Some(emitter) = self.clients.get_mut(&timed_event.player_id) else {
-                        continue;
-                    };
-
-                    let (pusher_pos, pusher_direction, pusher_id) =
-                        (emitter.position(), emitter.direction(), emitter.id());
-
-                    let players_on_same_pos: Vec<_> = self
-                        .clients
-                        .iter_mut()
-                        .filter_map(|(_, player)| {
-                            if player.position() == pusher_pos && player.id() != pusher_id {
-                                Some(player)
-                            } else {
-                                None
-                            }
-                        })
-                        .collect();
-
-                    let offset = match pusher_direction {
-                        Direction::North => (0, 1),
-                        Direction::East => (1, 0),
-                        Direction::South => (0, -1),
-                        Direction::West => (-1, 0),
-                    };
-                    let nb_pushed_players = players_on_same_pos.len();
-                    let new_pos = self
-                        .map
-                        .get_pos_with_offset(pusher_pos, Position::new(offset.0, offset.1));
-                    let direction: i8 = pusher_direction.into();
-                    for player in players_on_same_pos {
-                        player.position_mut().replace(new_pos);
-                        let pushed_dir: i8 = player.direction().into();
-                        let res = (direction - pushed_dir + 4).rem_euclid(4);
-                        let res = RELATIVE_DIRECTIONS[res as usize];
-                        //gui
-                        for (.., gui) in &self.guis {
-                            gui.send_to_client(ServerResponse::Gui(GUIResponse::Ppo(
-                                player.id(),
-                                player.position(),
-                                player.direction(),
-                            )));
-                        }
-                        player.send_to_client(ServerResponse::AI(AIResponse::Eject(res.into())));
-                    }
-                    let broken_eggs = self.map.break_eggs_at_pos(pusher_pos);
-                    let emitter = self.clients.get_mut(&timed_event.player_id).unwrap(); //safe since we know the player exists
-                    if nb_pushed_players > 0 || !broken_eggs.is_empty() {
-                        debug!(
-                            "Client {} broke {} eggs and pushed {} players",
-                            emitter.id(),
-                            broken_eggs.len(),
-                            nb_pushed_players
-                        );
-                        //gui
-                        for (.., gui) in &self.guis {
-                            gui.send_to_client(ServerResponse::Gui(GUIResponse::Pex(emitter.id())));
-                            for broken_egg in &broken_eggs {
-                                gui.send_to_client(ServerResponse::Gui(GUIResponse::Edi(
-                                    broken_egg.id(),
-                                )));
-                            }
-                        }
-
-                        emitter.send_to_client(ServerResponse::AI(AIResponse::Shared(
-                            SharedResponse::Ok,
-                        )));
-                    } else {
-                        emitter.send_to_client(ServerResponse::AI(AIResponse::Shared(
-                            SharedResponse::Ko,
-                        )));
-                    }
-                }
-                Event::Take(resource) => {
-                    let Some(emitter) = self.clients.get_mut(&timed_event.player_id) else {
-                        continue;
-                    };
-                    match self
-                        .map
-                        .del_resource(resource, 1, emitter.position(), &mut self.guis)
-                    {
-                        None => {
-                            emitter.send_to_client(ServerResponse::AI(AIResponse::Shared(
-                                SharedResponse::Ko,
-                            )));
-                        }
-                        Some(_) => {
-                            //gui
-                            for (.., gui) in &self.guis {
-                                gui.send_to_client(ServerResponse::Gui(GUIResponse::Pgt(
-                                    emitter.id(),
-                                    resource,
-                                )));
-                                gui.send_to_client(ServerResponse::Gui(GUIResponse::Pin(
-                                    emitter.id(),
-                                    emitter.position(),
-                                    emitter.inventory(),
-                                )));
-                                gui.send_to_client(ServerResponse::Gui(GUIResponse::Bct((
-                                    emitter.position(),
-                                    self.map[emitter.position()].ressources().clone(),
-                                ))));
-                            }
-
-                            emitter
-                                .add_resource(resource, 1)
-                                .send_to_client(ServerResponse::AI(AIResponse::Shared(
-                                    SharedResponse::Ok,
-                                )));
-                        }
-                    };
-                }
-                Event::Set(resource) => {
-                    let Some(emitter) = self.clients.get_mut(&timed_event.player_id) else {
-                        continue;
-                    };
-                    let res = emitter.del_resource(resource, 1);
-                    match res {
-                        None => {
-                            emitter.send_to_client(ServerResponse::AI(AIResponse::Shared(
-                                SharedResponse::Ko,
-                            )));
-                        }
-                        Some(resource) => {
-                            self.map
-                                .add_resource(resource, 1, emitter.position(), &mut self.guis);
-
-                            //gui
-                            for (.., gui) in &self.guis {
-                                gui.send_to_client(ServerResponse::Gui(GUIResponse::Pdr(
-                                    emitter.id(),
-                                    resource,
-                                )));
-                                gui.send_to_client(ServerResponse::Gui(GUIResponse::Pin(
-                                    emitter.id(),
-                                    emitter.position(),
-                                    emitter.inventory(),
-                                )));
-                                gui.send_to_client(ServerResponse::Gui(GUIResponse::Bct((
-                                    emitter.position(),
-                                    self.map[emitter.position()].ressources().clone(),
-                                ))));
-                            }
-                            emitter.send_to_client(ServerResponse::AI(AIResponse::Shared(
-                                SharedResponse::Ok,
-                            )));
-                        }
-                    }
-                }
-                Event::Incantation => {
-                    let Some(emitter) = self.clients.get_mut(&timed_event.player_id) else {
-                        continue;
-                    };
-                    let emitter_pos = emitter.position();
-                    let emitter_level = emitter.level();
-                    let emitter_id = emitter.id();
-                    debug!(
-                        "Incantation requirements for Client {}: {:?}",
-                        emitter.id(),
-                        LEVEL_REQUIREMENTS[&emitter_level]
-                    );
-                    let players_on_tile: Vec<Id> = self
-                        .clients
-                        .iter()
-                        .filter_map(|(id, player)| {
-                            if player.position() == emitter_pos
-                                && !player.is_incantating()
-                                && player.level() == emitter_level
-                            {
-                                Some(*id)
-                            } else {
-                                None
-                            }
-                        })
-                        .collect();
-
-                    let resources_on_tile: &Resources = self.map.get_ressources_at_pos(emitter_pos);
-                    let requirement = &LEVEL_REQUIREMENTS[&emitter_level];
-
-                    if players_on_tile.len() < requirement.needed_players()
-                        || !resources_on_tile.has_at_least(requirement.needed_resources())
-                    {
-                        let emitter = self.clients.get_mut(&timed_event.player_id).unwrap();
-                        emitter.send_to_client(ServerResponse::AI(AIResponse::Shared(
-                            SharedResponse::Ko,
-                        )));
-                        return;
-                    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constant::WINNING_PLAYERS;
+    use crate::protocol::{HasId, PendingAction, PendingEvent, SharedAction};
+    use crate::resources::ElevationLevel;
+    use tokio::io::AsyncReadExt;
+
+    fn test_config() -> ServerConfig {
+        ServerConfig::new(
+            "127.0.0.1".to_string(),
+            0,
+            10,
+            10,
+            vec!["team1".to_string(), "team2".to_string()],
+            4,
+            100,
+        )
+    }
 
-                    for id in &players_on_tile {
-                        let player = self.clients.get_mut(id).unwrap();
-                        *player.state_mut() = PlayerState::Incantating;
-                        player.send_to_client(ServerResponse::AI(AIResponse::Incantating));
-                        if *id != emitter_id {
-                            self.event_scheduler.shift_client_events(*id, 300);
-                            self.event_scheduler
-                                .force_schedule(Event::Phantom, 300, *id);
-                        }
-                        println!("Player {} is now {:?}", id, player.state_mut());
-                    }
+    async fn read_exact_string(stream: &mut TcpStream, len: usize) -> String {
+        let mut buf = vec![0u8; len];
+        stream.read_exact(&mut buf).await.unwrap();
+        String::from_utf8(buf).unwrap()
+    }
 
-                    let emitter = self.clients.get_mut(&timed_event.player_id).unwrap();
+    #[tokio::test]
+    async fn test_connection_limit_refuses_extra_client() {
+        let mut server = Server::from_config(test_config().max_connections(2))
+            .await
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let clients = async {
+            let mut first = TcpStream::connect(addr).await.unwrap();
+            assert_eq!(read_exact_string(&mut first, 8).await, "WELCOME\n");
+            let mut second = TcpStream::connect(addr).await.unwrap();
+            assert_eq!(read_exact_string(&mut second, 8).await, "WELCOME\n");
+
+            let mut third = TcpStream::connect(addr).await.unwrap();
+            let mut refused = String::new();
+            third.read_to_string(&mut refused).await.unwrap();
+            assert_eq!(refused, "ko\n");
+        };
 
-                    //gui
-                    for (.., gui) in &self.guis {
-                        gui.send_to_client(ServerResponse::Gui(GUIResponse::Pic(
-                            emitter_pos,
-                            emitter.level(),
-                            players_on_tile.clone(),
-                        )));
-                    }
+        select! {
+            _ = server.run() => unreachable!(),
+            _ = clients => {}
+        }
+    }
 
-                    let new_event =
-                        Event::IncantationEnd(players_on_tile, requirement, emitter.position());
-                    self.event_scheduler.schedule(new_event, 300, emitter.id());
-                }
-                Event::IncantationEnd(players_incantating, requirement, incantation_pos) => {
-                    let mut players_still_on_tile: Vec<Id> = vec![];
-
-                    for id in &players_incantating {
-                        if let Some(player) = self.clients.get_mut(id) {
-                            if player.is_incantating() && player.position() == incantation_pos {
-                                *player.state_mut() = PlayerState::Idle;
-                                players_still_on_tile.push(*id);
-                            }
-                        }
-                    }
+    #[tokio::test]
+    async fn test_server_reports_its_config() {
+        let server = Server::from_config(test_config()).await.unwrap();
+        let config = server.config();
 
-                    let resources_on_tile: &Resources =
-                        self.map.get_ressources_at_pos(incantation_pos);
-
-                    if players_still_on_tile.len() < requirement.needed_players()
-                        || !resources_on_tile.has_at_least(requirement.needed_resources())
-                    {
-                        //gui
-                        for (.., gui) in &self.guis {
-                            gui.send_to_client(ServerResponse::Gui(GUIResponse::Pie(
-                                incantation_pos,
-                                false,
-                            )));
-                        }
-
-                        for id in &players_incantating {
-                            if let Some(client) = self.clients.get_mut(id) {
-                                client.send_to_client(ServerResponse::AI(AIResponse::Shared(
-                                    SharedResponse::Ko,
-                                )));
-                            }
-                        }
-                        return;
-                    }
-                    for resource_type in Resource::iter() {
-                        let amount = requirement.needed_resources()[resource_type];
-                        if amount > 0 {
-                            self.map.del_resource(
-                                resource_type,
-                                amount,
-                                incantation_pos,
-                                &mut self.guis,
-                            );
-                        }
-                    }
-                    for id in &players_still_on_tile {
-                        let client = self.clients.get_mut(id).unwrap();
-                        *client.level_mut() = client.level().upgrade();
-                        client.send_to_client(ServerResponse::AI(AIResponse::LevelUp(
-                            client.level(),
-                        )));
-
-                        //gui
-                        for (.., gui) in &self.guis {
-                            gui.send_to_client(ServerResponse::Gui(GUIResponse::Plv(
-                                client.id(),
-                                client.level(),
-                            )));
-                        }
-                    }
+        assert_eq!(config.addr(), "127.0.0.1");
+        assert_eq!((config.width(), config.height()), (10, 10));
+        assert_eq!(config.teams(), ["team1", "team2"]);
+        assert_eq!(config.clients_nb(), 4);
+        assert_eq!(config.freq(), 100);
+    }
 
-                    //gui
-                    for (.., gui) in &self.guis {
-                        gui.send_to_client(ServerResponse::Gui(GUIResponse::Pie(
-                            incantation_pos,
-                            true,
-                        )));
-                    }
-                    debug!(
-                        "Incantation successful for Clients : {:?}",
-                        players_still_on_tile
-                    );
-                }
-                Event::Ko => {
-                    if let Some(client) = self.clients.get_mut(&timed_event.player_id) {
-                        client.send_to_client(ServerResponse::AI(AIResponse::Shared(
-                            SharedResponse::Ko,
-                        )));
-                    } else {
-                        continue;
-                    }
-                }
-                Event::Phantom => continue,
-            }
+    #[tokio::test]
+    async fn test_ai_login_handshake_bytes() {
+        let mut server = Server::from_config(test_config()).await.unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let client = async {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            assert_eq!(read_exact_string(&mut stream, 8).await, "WELCOME\n");
+            stream.write_all(b"team1\n").await.unwrap();
+            assert_eq!(read_exact_string(&mut stream, 8).await, "3\n10 10\n");
+        };
+
+        select! {
+            _ = server.run() => unreachable!(),
+            _ = client => {}
         }
-        self.reduce_satiety();
     }
 
-    pub fn reduce_satiety(&mut self) {
-        for (id, client) in self.clients.iter_mut() {
-            if client.reduce_satiety(SATIETY_LOSS_PER_TICK) == 0 {
-                client.send_to_client(ServerResponse::AI(AIResponse::Dead));
-                info!("Client {} is dead", id);
+    #[tokio::test]
+    async fn test_welcome_banner_is_optional() {
+        async fn greeting(config: ServerConfig, len: usize) -> String {
+            let mut server = Server::from_config(config).await.unwrap();
+            let addr = server.local_addr().unwrap();
+            let client = async {
+                let mut stream = TcpStream::connect(addr).await.unwrap();
+                read_exact_string(&mut stream, len).await
+            };
+            select! {
+                _ = server.run() => unreachable!(),
+                greeting = client => greeting,
             }
         }
 
-        // Notify GUIs if at least 1 second passed
-        if self.last_gui_notify.elapsed() >= Duration::from_secs(1) {
-            self.last_gui_notify = Instant::now();
-
-            for client in self.clients.values() {
-                for (.., gui) in &self.guis {
-                    gui.send_to_client(ServerResponse::Gui(GUIResponse::Pin(
-                        client.id(),
-                        client.position(),
-                        client.inventory(),
-                    )));
-                }
-            }
+        assert_eq!(greeting(test_config(), 8).await, "WELCOME\n");
+        assert_eq!(
+            greeting(test_config().banner("zappy-rs 1.0".to_string()), 21).await,
+            "WELCOME\nzappy-rs 1.0\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_starved_player_reads_dead_before_eof() {
+        let config = test_config().max_satiety(1);
+        let mut server = Server::from_config(config).await.unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let client = async {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(b"team1\n").await.unwrap();
+            let mut received = String::new();
+            stream.read_to_string(&mut received).await.unwrap();
+            assert_eq!(received, "WELCOME\n3\n10 10\ndead\n");
+        };
+
+        select! {
+            _ = server.run() => unreachable!(),
+            _ = client => {}
         }
     }
 
-    async fn process_events(&mut self, event: EventType) {
-        debug!("Event {:?}", event);
-        match event {
-            EventType::AI(GameEvent { id, action }) => {
-                self.handle_ai_events((id, action)).await;
-            }
-            EventType::GUI(GameEvent { id, action }) => {
-                self.handle_gui_events((id, action)).await;
-            }
-            EventType::Pending(GameEvent { id, action }) => {
-                self.handle_pending_events((id, action)).await;
+    #[tokio::test]
+    async fn test_stopped_server_flushes_dead_and_seg_before_eof() {
+        let config = ServerConfig::new(
+            "127.0.0.1".to_string(),
+            0,
+            10,
+            10,
+            vec!["team1".to_string(), "team2".to_string()],
+            WINNING_PLAYERS,
+            100,
+        )
+        .end_action(EndAction::Stop);
+        let mut server = Server::from_config(config).await.unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let clients = async {
+            let mut players = Vec::new();
+            for _ in 0..WINNING_PLAYERS {
+                let mut stream = TcpStream::connect(addr).await.unwrap();
+                stream.write_all(b"team1\n").await.unwrap();
+                read_exact_string(&mut stream, 16).await;
+                players.push(stream);
             }
+            let mut gui = TcpStream::connect(addr).await.unwrap();
+            gui.write_all(b"GRAPHIC\n").await.unwrap();
+            assert_eq!(
+                read_exact_string(&mut gui, 18).await,
+                "WELCOME\nmsz 10 10\n"
+            );
+            (players, gui)
+        };
+        let (players, mut gui) = select! {
+            _ = server.run() => unreachable!(),
+            streams = clients => streams,
+        };
+
+        let ids: Vec<Id> = server.game.players().map(|player| player.id()).collect();
+        for id in ids {
+            *server.game.player_mut(id).unwrap().level_mut() = ElevationLevel::Level8;
+        }
+        server.run().await.unwrap();
+
+        for mut player in players {
+            let mut received = String::new();
+            player.read_to_string(&mut received).await.unwrap();
+            assert_eq!(received, "dead\n");
         }
+        let mut received = String::new();
+        gui.read_to_string(&mut received).await.unwrap();
+        assert!(received.contains("seg team1\n"), "{:?}", received);
     }
 
-    async fn handle_pending_events(&mut self, (id, action): (Id, PendingAction)) {
-        let Some(client) = self.pending_clients.get_mut(&id) else {
-            warn!(
-                "This client is not pending anymore : {}, cancelled event {:?}",
-                id, action
+    #[tokio::test]
+    async fn test_command_sent_with_the_login_is_answered() {
+        let mut server = Server::from_config(test_config()).await.unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let client = async {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(b"Nope\nteam1\nForward\n").await.unwrap();
+            assert_eq!(
+                read_exact_string(&mut stream, 22).await,
+                "WELCOME\nko\n3\n10 10\nok\n"
             );
-            return;
         };
 
-        fn send_ko(client: &mut impl ClientSender) {
-            client.send_to_client(ServerResponse::Pending(Shared(SharedResponse::Ko)));
+        select! {
+            _ = server.run() => unreachable!(),
+            _ = client => {}
         }
+    }
 
-        match action {
-            PendingAction::Shared(SharedAction::Disconnected) => {
-                self.pending_clients.remove_entry(&id);
-                info!("Pending client: {} disconnected", id);
-            }
-            PendingAction::Shared(
-                SharedAction::InvalidAction | SharedAction::InvalidParameters,
-            ) => unreachable!(),
-            PendingAction::Shared(SharedAction::ReachedTakeLimit) => {
-                warn!("Pending client: {} sent too much data", id);
-                send_ko(client);
-            }
-            PendingAction::Shared(SharedAction::InvalidEncoding) => {
-                warn!("Pending client: {} uses invalid encoding", id);
-                send_ko(client);
-            }
-            PendingAction::Login(team_name) => {
-                if team_name == "GRAPHIC" {
-                    let pending_client = self.pending_clients.remove(&id).unwrap();
-
-                    let new_gui = GuiBuilder::new()
-                        .pending_client(pending_client)
-                        .build()
-                        .unwrap();
-                    new_gui.send_to_client(ServerResponse::Pending(LogAs(TeamType::Graphic)));
-                    self.guis.insert(id, new_gui);
-                    return;
-                }
-
-                let Some(team) = self.teams.values().find(|team| team.name() == team_name) else {
-                    send_ko(client);
-                    return;
-                };
-
-                if self.map.nb_eggs_by_team(team.id()) == 0 {
-                    warn!(
-                        "Client {} can't login: team '{}' has no eggs",
-                        id, team_name
-                    );
-                    send_ko(client);
-                    return;
-                }
-
-                let egg = self.map.drop_egg(team.id()).unwrap();
-                let pending_client = self.pending_clients.remove(&id).unwrap();
-
-                let player_builder = Player::builder()
-                    .team(team.id())
-                    .pending_client(pending_client)
-                    .position(egg.position());
-
-                let player = player_builder.build().unwrap();
-                player.send_to_client(ServerResponse::Pending(LogAs(TeamType::IA(
-                    self.map.nb_eggs_by_team(team.id()),
-                    self.map.size(),
-                ))));
-
-                // gui
-                for (.., gui) in &self.guis {
-                    gui.send_to_client(ServerResponse::Gui(GUIResponse::Pnw(
-                        player.id(),
-                        player.position(),
-                        player.direction(),
-                        player.level(),
-                        team_name.clone(),
-                    )));
-                    gui.send_to_client(ServerResponse::Gui(GUIResponse::Ebo(egg.id())));
-                }
-
-                self.clients.insert(player.id(), player);
-            }
+    #[tokio::test]
+    async fn test_unreadable_line_sent_with_the_login_is_answered_after_it() {
+        let mut server = Server::from_config(test_config()).await.unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let client = async {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream
+                .write_all(b"team1\n\xff\xfe\nForward\n")
+                .await
+                .unwrap();
+            assert_eq!(
+                read_exact_string(&mut stream, 22).await,
+                "WELCOME\n3\n10 10\nko\nok\n"
+            );
+        };
+
+        select! {
+            _ = server.run() => unreachable!(),
+            _ = client => {}
         }
     }
 
-    async fn handle_ai_events(&mut self, (id, action): (Id, AIAction)) {
-        match action {
-            AIAction::Shared(shared) => match shared {
-                SharedAction::Disconnected => {
-                    for (.., gui) in &self.guis {
-                        gui.send_to_client(ServerResponse::Gui(GUIResponse::Pdi(id)));
-                    }
-                    self.clients.remove(&id);
-                }
-                SharedAction::InvalidAction
-                | SharedAction::ReachedTakeLimit
-                | SharedAction::InvalidEncoding
-                | SharedAction::InvalidParameters => {
-                    self.event_scheduler.schedule(Event::Ko, 0, id);
-                }
-            },
-            AIAction::Action(action) => match action {
-                event @ (Event::Broadcast(_)
-                | Event::Forward
-                | Event::Right
-                | Event::Left
-                | Event::Look
-                | Event::Take(_)
-                | Event::Set(_)
-                | Event::Eject) => {
-                    self.event_scheduler.schedule(event, 7, id);
-                }
-                event @ Event::Inventory => {
-                    self.event_scheduler.schedule(event, 1, id);
-                }
-                event @ Event::ConnectNbr => {
-                    self.event_scheduler.schedule(event, 0, id);
-                }
-                event @ Event::Fork => {
-                    self.event_scheduler.schedule(event, 42, id);
-                }
-                event @ Event::Incantation => {
-                    self.event_scheduler.schedule(event, 0, id);
-                }
-                _ => {
-                    unreachable!()
-                }
-            },
-        }
+    #[tokio::test]
+    async fn test_tick_interval_skips_missed_ticks_after_sst() {
+        let mut server = Server::from_config(test_config()).await.unwrap();
+        assert_eq!(
+            server.tick_interval.missed_tick_behavior(),
+            time::MissedTickBehavior::Skip
+        );
+        assert_eq!(
+            server.tick_interval.period(),
+            time::Duration::from_millis(10)
+        );
+
+        server.set_tick_interval(50);
+
+        assert_eq!(
+            server.tick_interval.missed_tick_behavior(),
+            time::MissedTickBehavior::Skip
+        );
+        assert_eq!(
+            server.tick_interval.period(),
+            time::Duration::from_millis(20)
+        );
     }
 
-    async fn handle_gui_events(&mut self, (id, action): (Id, GUIAction)) {
-        match action {
-            GUIAction::Shared(shared) => match shared {
-                SharedAction::Disconnected => {
-                    self.guis.remove(&id);
-                }
-                SharedAction::InvalidAction
-                | SharedAction::ReachedTakeLimit
-                | SharedAction::InvalidEncoding => {
-                    if let Some(emitter) = self.guis.get_mut(&id) {
-                        emitter.send_to_client(ServerResponse::Gui(GUIResponse::Shared(
-                            SharedResponse::Ko,
-                        )));
-                    }
-                }
-                SharedAction::InvalidParameters => {
-                    if let Some(emitter) = self.guis.get_mut(&id) {
-                        emitter.send_to_client(ServerResponse::Gui(GUIResponse::Sbp));
-                    }
-                }
-            },
-            GUIAction::Msz => {
-                if let Some(emitter) = self.guis.get_mut(&id) {
-                    emitter.send_to_client(ServerResponse::Gui(GUIResponse::Msz(self.map.size())));
-                }
-            }
-            GUIAction::Bct(pos) => {
-                if let Some(emitter) = self.guis.get_mut(&id) {
-                    let Some(cell) = self.map.get(pos) else {
-                        emitter.send_to_client(ServerResponse::Gui(GUIResponse::Sbp));
-                        return;
-                    };
-                    emitter.send_to_client(ServerResponse::Gui(GUIResponse::Bct((
-                        pos,
-                        cell.ressources().clone(),
-                    ))));
-                }
-            }
-            GUIAction::Mct => {
-                if let Some(emitter) = self.guis.get_mut(&id) {
-                    let bct_responses: Vec<BctResponse> = self
-                        .map
-                        .cells_with_positions()
-                        .map(|(pos, cell)| (pos, cell.ressources().clone()))
-                        .collect();
-
-                    emitter.send_to_client(ServerResponse::Gui(GUIResponse::Mct(bct_responses)));
-                }
-            }
-            GUIAction::Tna => {
-                if let Some(emitter) = self.guis.get_mut(&id) {
-                    let team_name = self
-                        .teams
-                        .iter()
-                        .map(|(.., team_name)| team_name.name().to_string())
-                        .collect::<Vec<_>>();
-                    emitter.send_to_client(ServerResponse::Gui(GUIResponse::Tna(team_name)));
-                }
-            }
-            GUIAction::Ppo(player_id) => {
-                if let Some(emitter) = self.guis.get_mut(&id) {
-                    if let Some(player) = self.clients.get(&player_id) {
-                        emitter.send_to_client(ServerResponse::Gui(GUIResponse::Ppo(
-                            player_id,
-                            player.position(),
-                            player.direction(),
-                        )));
-                    } else {
-                        emitter.send_to_client(ServerResponse::Gui(GUIResponse::Sbp));
-                    }
-                }
-            }
-            GUIAction::Plv(player_id) => {
-                if let Some(emitter) = self.guis.get_mut(&id) {
-                    if let Some(player) = self.clients.get(&player_id) {
-                        emitter.send_to_client(ServerResponse::Gui(GUIResponse::Plv(
-                            player_id,
-                            player.level(),
-                        )));
-                    } else {
-                        emitter.send_to_client(ServerResponse::Gui(GUIResponse::Sbp));
-                    }
-                }
-            }
-            GUIAction::Pin(player_id) => {
-                if let Some(emitter) = self.guis.get_mut(&id) {
-                    if let Some(player) = self.clients.get(&player_id) {
-                        emitter.send_to_client(ServerResponse::Gui(GUIResponse::Pin(
-                            player_id,
-                            player.position(),
-                            player.inventory(),
-                        )));
-                    } else {
-                        emitter.send_to_client(ServerResponse::Gui(GUIResponse::Sbp));
-                    }
-                }
-            }
-            GUIAction::Sgt => {
-                if let Some(emitter) = self.guis.get_mut(&id) {
-                    let freq =
-                        (1_000_000_000f64 / self.tick_interval.period().as_nanos() as f64) as u64;
-                    emitter.send_to_client(ServerResponse::Gui(GUIResponse::Sgt(freq)));
-                }
-            }
-            GUIAction::Sst(freq) => {
-                if let Some(emitter) = self.guis.get_mut(&id) {
-                    let tick_interval = time::interval(time::Duration::from_nanos(
-                        (1_000_000_000f64 / freq as f64) as u64,
-                    ));
-                    self.tick_interval = tick_interval;
-                    emitter.send_to_client(ServerResponse::Gui(GUIResponse::Sst(freq)));
-                    for (.., gui) in &self.guis {
-                        gui.send_to_client(ServerResponse::Gui(GUIResponse::Sgt(
-                            freq
-                        )));
-                    }
-                }
-            }
+    #[tokio::test]
+    async fn test_ready_events_are_processed_in_bounded_batches() {
+        let mut server = Server::from_config(test_config().event_batch_size(3))
+            .await
+            .unwrap();
+        for id in 0..5 {
+            server
+                .global_channel
+                .tx
+                .send(EventType::Pending(PendingEvent {
+                    id,
+                    action: PendingAction::Shared(SharedAction::Disconnected),
+                }))
+                .await
+                .unwrap();
         }
+
+        let first = server.global_channel.rx.recv().await.unwrap();
+        server.process_events(first).await;
+        server.process_ready_events().await;
+        assert_eq!(server.global_channel.rx.len(), 2);
+
+        let first = server.global_channel.rx.recv().await.unwrap();
+        server.process_events(first).await;
+        server.process_ready_events().await;
+        assert!(server.global_channel.rx.is_empty());
     }
 }
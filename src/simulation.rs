@@ -0,0 +1,306 @@
+use crate::formater::{IdFormat, InventoryFormat, LevelFormat};
+use crate::game::Game;
+use crate::handler::ai::AiHandler;
+use crate::handler::command::CommandHandler;
+use crate::pending::PendingClient;
+use crate::protocol::{
+    AIResponse, EventType, GameEvent, HasId, Id, PendingAction, ServerResponse, SharedAction,
+};
+use crate::resources::{ElevationLevel, Resources};
+use crate::server::{ServerConfig, ServerError};
+use std::collections::VecDeque;
+use std::fmt;
+use tokio::sync::mpsc;
+
+/// An AI whose commands are known in advance
+struct Script {
+    handler: AiHandler,
+    commands: VecDeque<String>,
+    rx: mpsc::Receiver<ServerResponse>,
+    dead: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerReport {
+    pub id: Id,
+    pub team: String,
+    pub level: ElevationLevel,
+}
+
+#[derive(Debug, Clone)]
+pub struct SimulationReport {
+    pub tick: u64,
+    pub survivors: Vec<PlayerReport>,
+    pub resources: Resources,
+}
+
+impl fmt::Display for SimulationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "tick {}", self.tick)?;
+        for player in &self.survivors {
+            writeln!(
+                f,
+                "player {} {} level {}",
+                IdFormat(&player.id),
+                player.team,
+                LevelFormat(&player.level)
+            )?;
+        }
+        writeln!(f, "resources {}", InventoryFormat(&self.resources))
+    }
+}
+
+/// Scripted AIs of a `--simulate` file, one per non-empty line written
+/// `<team>: <command>; <command>; ...`, lines starting with `#` are comments
+pub fn parse_script(script: &str) -> Result<Vec<(String, Vec<String>)>, String> {
+    script
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| {
+            let (team, commands) = line
+                .split_once(':')
+                .ok_or_else(|| format!("line {}: expected `<team>: <commands>`", number))?;
+            let commands = commands
+                .split(';')
+                .map(str::trim)
+                .filter(|command| !command.is_empty())
+                .map(str::to_string)
+                .collect();
+            Ok((team.trim().to_string(), commands))
+        })
+        .collect()
+}
+
+/// Runs a [`Game`] without sockets, every scripted AI sends its next command
+/// as soon as the previous one was executed, like a client waiting for answers
+pub struct Simulation {
+    game: Game,
+    scripts: Vec<Script>,
+    next_id: Id,
+}
+
+impl Simulation {
+    pub fn from_config(config: &ServerConfig) -> Result<Simulation, ServerError> {
+        Ok(Simulation {
+            game: Game::from_config(config)?,
+            scripts: Vec::new(),
+            next_id: 0,
+        })
+    }
+
+    /// Logs in every AI of a [`parse_script`] script, returns the teams that refused one
+    pub async fn add_script(&mut self, players: &[(String, Vec<String>)]) -> Vec<String> {
+        let mut refused = Vec::new();
+        for (team, commands) in players {
+            let commands: Vec<&str> = commands.iter().map(String::as_str).collect();
+            if self.add_player(team, &commands).await.is_none() {
+                refused.push(team.clone());
+            }
+        }
+        refused
+    }
+
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+
+    /// Logs a scripted AI into `team_name`, returns its id or `None` if the login is refused
+    pub async fn add_player(&mut self, team_name: &str, commands: &[&str]) -> Option<Id> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let (client_tx, rx) = mpsc::channel(1024);
+        self.game.add_pending_client(PendingClient {
+            client_id: id,
+            client_tx,
+        });
+        self.game
+            .process_events(EventType::Pending(GameEvent {
+                id,
+                action: PendingAction::Login(team_name.to_string()),
+            }))
+            .await;
+
+        if !self.game.players().any(|player| player.id() == id) {
+            self.game
+                .process_events(EventType::Pending(GameEvent {
+                    id,
+                    action: PendingAction::Shared(SharedAction::Disconnected),
+                }))
+                .await;
+            return None;
+        }
+
+        self.scripts.push(Script {
+            handler: AiHandler::new(id),
+            commands: commands.iter().map(|cmd| cmd.to_string()).collect(),
+            rx,
+            dead: false,
+        });
+        Some(id)
+    }
+
+    /// Runs `ticks` ticks of the game then reports its final state
+    pub async fn run(&mut self, ticks: u64) -> SimulationReport {
+        for _ in 0..ticks {
+            self.feed_commands().await;
+            self.game.tick_once().await;
+            self.collect_deaths().await;
+        }
+        self.report()
+    }
+
+    async fn feed_commands(&mut self) {
+        for script in self.scripts.iter_mut().filter(|script| !script.dead) {
+            if self.game.is_busy(script.handler.id()) {
+                continue;
+            }
+            if let Some(command) = script.commands.pop_front() {
+                let event = script.handler.parse_command(command);
+                self.game.process_events(event).await;
+            }
+        }
+    }
+
+    /// Disconnects the AIs told they are dead, as their connection would
+    async fn collect_deaths(&mut self) {
+        for script in self.scripts.iter_mut().filter(|script| !script.dead) {
+            while let Ok(response) = script.rx.try_recv() {
+                if matches!(response, ServerResponse::AI(AIResponse::Dead)) {
+                    script.dead = true;
+                }
+            }
+            if script.dead {
+                let event = script
+                    .handler
+                    .create_shared_event(SharedAction::Disconnected);
+                self.game.process_events(event).await;
+            }
+        }
+    }
+
+    fn report(&self) -> SimulationReport {
+        let mut survivors: Vec<PlayerReport> = self
+            .game
+            .players()
+            .map(|player| PlayerReport {
+                id: player.id(),
+                team: self
+                    .game
+                    .team(player.team_id())
+                    .map(|team| team.name().to_string())
+                    .unwrap_or_default(),
+                level: player.level(),
+            })
+            .collect();
+        survivors.sort_by_key(|player| player.id);
+
+        SimulationReport {
+            tick: self.game.current_tick(),
+            survivors,
+            resources: self.game.map().resources().clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config() -> ServerConfig {
+        ServerConfig::new(
+            "127.0.0.1".to_string(),
+            0,
+            10,
+            10,
+            vec!["team1".to_string(), "team2".to_string()],
+            1,
+            100,
+        )
+        .seed(42)
+    }
+
+    #[tokio::test]
+    async fn test_scripted_players_run_without_sockets() {
+        let mut simulation = Simulation::from_config(&test_config()).unwrap();
+        let first = simulation
+            .add_player("team1", &["Forward", "Right", "Forward"])
+            .await
+            .unwrap();
+        assert!(simulation.add_player("team1", &[]).await.is_none());
+        let second = simulation.add_player("team2", &[]).await.unwrap();
+
+        let report = simulation.run(30).await;
+
+        assert_eq!(report.tick, 30);
+        assert_eq!(
+            report.survivors,
+            vec![
+                PlayerReport {
+                    id: first,
+                    team: "team1".to_string(),
+                    level: ElevationLevel::Level1,
+                },
+                PlayerReport {
+                    id: second,
+                    team: "team2".to_string(),
+                    level: ElevationLevel::Level1,
+                },
+            ]
+        );
+        assert!(!simulation.game().is_busy(first));
+    }
+
+    #[tokio::test]
+    async fn test_starving_players_are_removed() {
+        let mut simulation =
+            Simulation::from_config(&test_config().max_satiety(1).idle_timeout(None)).unwrap();
+        simulation.add_player("team1", &[]).await.unwrap();
+
+        let report = simulation.run(20).await;
+
+        assert!(report.survivors.is_empty());
+    }
+
+    #[test]
+    fn test_parse_script() {
+        let script = "# two scouts\nteam1: Forward; Right ;Look;\n\nteam2:\n";
+
+        assert_eq!(
+            parse_script(script).unwrap(),
+            vec![
+                (
+                    "team1".to_string(),
+                    vec![
+                        "Forward".to_string(),
+                        "Right".to_string(),
+                        "Look".to_string()
+                    ]
+                ),
+                ("team2".to_string(), vec![]),
+            ]
+        );
+        assert_eq!(
+            parse_script("team1: Forward\nForward").unwrap_err(),
+            "line 2: expected `<team>: <commands>`"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_script_report() {
+        let mut simulation = Simulation::from_config(&test_config()).unwrap();
+        let players = parse_script("team1: Forward\nteam1: Look\nteam2: Inventory").unwrap();
+
+        assert_eq!(simulation.add_script(&players).await, ["team1"]);
+        let report = simulation.run(10).await.to_string();
+
+        let mut lines = report.lines();
+        assert_eq!(lines.next(), Some("tick 10"));
+        assert_eq!(lines.next(), Some("player #0 team1 level 1"));
+        assert_eq!(lines.next(), Some("player #2 team2 level 1"));
+        assert!(lines.next().unwrap().starts_with("resources [food "));
+        assert_eq!(lines.next(), None);
+    }
+}
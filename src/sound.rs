@@ -1,8 +1,13 @@
 use crate::player::{Direction, Player};
 use crate::vec2::Size;
-use crate::vec2::{HasPosition, UPosition};
+use crate::vec2::{HasPosition, UPosition, toroidal_delta};
+use log::trace;
 use std::f64::consts::PI;
 
+/// Log target of the sound direction computations, enable it with
+/// `RUST_LOG=zappy::sound=trace`
+pub const SOUND_LOG_TARGET: &str = "zappy::sound";
+
 pub struct Emitter {
     pos: UPosition,
 }
@@ -29,54 +34,23 @@ impl From<&Player> for Receiver {
     }
 }
 
-/// Returns the shortest path between two points on a torus.
-/// * `start` - The starting position.
-/// * `end` - The ending position.
-/// * `size` - The size of the torus.
-/// # Examples
-/// ```
-/// use crate::vec2::Position;
-/// use crate::vec2::Size;
-/// use crate::sound::get_shortest_path_torique;
-/// let map_size = Size::new(10, 8);
-/// let start = Position::new(0, 6);
-/// let end = Position::new(9, 3);
-/// let (dx, dy) = get_shortest_path_torique(start, end, map_size);
-/// assert_eq!(dx, -1);
-/// assert_eq!(dy, -3);
-/// ```
-/// * `return` - A tuple containing the shortest path in the x and y directions starting from the start position.
-fn get_shortest_path_torique(start: UPosition, end: UPosition, size: Size) -> (i64, i64) {
-    let (dx, dy) = (
-        (end.x() as i64 - start.x() as i64).rem_euclid(size.x() as i64),
-        (end.y() as i64 - start.y() as i64).rem_euclid(size.y() as i64),
-    );
-
-    let dx = if dx > size.x() as i64 / 2 {
-        dx - size.x() as i64
-    } else {
-        dx
-    };
-    let dy = if dy > size.y() as i64 / 2 {
-        dy - size.y() as i64
-    } else {
-        dy
-    };
-
-    (dx, dy)
-}
-
 pub fn get_sound_direction(emitter: Emitter, receiver: Receiver, size: Size) -> u8 {
     if emitter.pos == receiver.pos {
+        trace!(
+            target: SOUND_LOG_TARGET,
+            "emitter {:?} on receiver tile {:?}: direction 0",
+            emitter.pos,
+            receiver.pos
+        );
         return 0;
     }
-    let (dx, dy) = get_shortest_path_torique(receiver.pos, emitter.pos, size);
+    let (dx, dy) = toroidal_delta(receiver.pos, emitter.pos, size);
     let mut global_angle = (dy as f64).atan2(dx as f64);
     if global_angle < 0.0 {
         global_angle += 2.0 * PI;
     }
     let dir = (global_angle / (PI / 4.0)).round_ties_even() as i64;
-    ((dir
+    let result = ((dir
         + match receiver.direction {
             Direction::East => 0,
             Direction::North => 6,
@@ -84,7 +58,18 @@ pub fn get_sound_direction(emitter: Emitter, receiver: Receiver, size: Size) ->
             Direction::West => 4,
         })
     .rem_euclid(8)
-        + 1) as u8
+        + 1) as u8;
+    trace!(
+        target: SOUND_LOG_TARGET,
+        "emitter {:?}, receiver {:?} facing {:?}: dx {} dy {}, direction {}",
+        emitter.pos,
+        receiver.pos,
+        receiver.direction,
+        dx,
+        dy,
+        result
+    );
+    result
 }
 
 #[cfg(test)]
@@ -245,130 +230,4 @@ mod tests {
 
         assert_eq!(direction, 4);
     }
-
-    #[test]
-    fn test_shortest_path() {
-        let map_size = Size::new(10, 8);
-
-        let emitter = Emitter {
-            pos: UPosition::new(0, 6),
-        };
-        let receiver = Receiver {
-            pos: UPosition::new(9, 3),
-            direction: Direction::North,
-        };
-
-        let (dx, dy) = get_shortest_path_torique(emitter.pos, receiver.pos, map_size);
-
-        assert_eq!(dx, -1);
-        assert_eq!(dy, -3);
-    }
-
-    #[test]
-    fn test_shortest_path_wraparound() {
-        let map_size = Size::new(10, 8);
-
-        let emitter = Emitter {
-            pos: UPosition::new(9, 3),
-        };
-        let receiver = Receiver {
-            pos: UPosition::new(0, 6),
-            direction: Direction::North,
-        };
-
-        let (dx, dy) = get_shortest_path_torique(emitter.pos, receiver.pos, map_size);
-
-        assert_eq!(dx, 1);
-        assert_eq!(dy, 3);
-    }
-
-    #[test]
-    fn test_shortest_path_wraparound_negative() {
-        let map_size = Size::new(10, 8);
-
-        let emitter = Emitter {
-            pos: UPosition::new(0, 3),
-        };
-        let receiver = Receiver {
-            pos: UPosition::new(9, 6),
-            direction: Direction::North,
-        };
-
-        let (dx, dy) = get_shortest_path_torique(emitter.pos, receiver.pos, map_size);
-
-        assert_eq!(dx, -1);
-        assert_eq!(dy, 3);
-    }
-
-    #[test]
-    fn test_shortest_path_wraparound_negative_y() {
-        let map_size = Size::new(10, 8);
-
-        let emitter = Emitter {
-            pos: UPosition::new(5, 0),
-        };
-        let receiver = Receiver {
-            pos: UPosition::new(5, 7),
-            direction: Direction::North,
-        };
-
-        let (dx, dy) = get_shortest_path_torique(emitter.pos, receiver.pos, map_size);
-
-        assert_eq!(dx, 0);
-        assert_eq!(dy, -1);
-    }
-
-    #[test]
-    fn test_shortest_path_wraparound_negative_x() {
-        let map_size = Size::new(10, 8);
-
-        let emitter = Emitter {
-            pos: UPosition::new(0, 3),
-        };
-        let receiver = Receiver {
-            pos: UPosition::new(9, 3),
-            direction: Direction::North,
-        };
-
-        let (dx, dy) = get_shortest_path_torique(emitter.pos, receiver.pos, map_size);
-
-        assert_eq!(dx, -1);
-        assert_eq!(dy, 0);
-    }
-
-    #[test]
-    fn test_shortest_path_wraparound_negative_x_y() {
-        let map_size = Size::new(10, 8);
-
-        let emitter = Emitter {
-            pos: UPosition::new(0, 0),
-        };
-        let receiver = Receiver {
-            pos: UPosition::new(9, 7),
-            direction: Direction::North,
-        };
-
-        let (dx, dy) = get_shortest_path_torique(emitter.pos, receiver.pos, map_size);
-
-        assert_eq!(dx, -1);
-        assert_eq!(dy, -1);
-    }
-
-    #[test]
-    fn test_shortest_path_wraparound_negative_x_y_2() {
-        let map_size = Size::new(10, 8);
-
-        let emitter = Emitter {
-            pos: UPosition::new(9, 7),
-        };
-        let receiver = Receiver {
-            pos: UPosition::new(0, 0),
-            direction: Direction::North,
-        };
-
-        let (dx, dy) = get_shortest_path_torique(emitter.pos, receiver.pos, map_size);
-
-        assert_eq!(dx, 1);
-        assert_eq!(dy, 1);
-    }
 }
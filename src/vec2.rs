@@ -1,4 +1,6 @@
-#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+use std::ops::{Add, Sub};
+
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
 pub struct Vec2<T> {
     x: T,
     y: T,
@@ -44,6 +46,36 @@ impl<T: Copy> Vec2<T> {
     }
 }
 
+impl Add for Vec2<i64> {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Vec2::new(self.x + other.x, self.y + other.y)
+    }
+}
+
+impl Sub for Vec2<i64> {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Vec2::new(self.x - other.x, self.y - other.y)
+    }
+}
+
+impl Vec2<u64> {
+    /// Moves by `offset` on a map of `size`, wrapping around its edges
+    pub fn wrapping_add(self, offset: Position, size: Size) -> UPosition {
+        let wrap = |value: u64, offset: i64, len: u64| {
+            (value as i64 + offset).rem_euclid(len as i64) as u64
+        };
+
+        Vec2::new(
+            wrap(self.x, offset.x, size.x),
+            wrap(self.y, offset.y, size.y),
+        )
+    }
+}
+
 impl Default for Vec2<u64> {
     fn default() -> Self {
         Vec2::new(0, 0)
@@ -57,6 +89,27 @@ impl From<(u64, u64)> for Vec2<u64> {
     }
 }
 
+/// Shortest signed offset from `start` to `end` on a map that wraps around,
+/// ties (exactly half the map) go in the positive direction
+pub fn toroidal_delta(start: UPosition, end: UPosition, size: Size) -> (i64, i64) {
+    let wrap = |from: u64, to: u64, len: u64| {
+        let len = len as i64;
+        let delta = (to as i64 - from as i64).rem_euclid(len);
+        if delta > len / 2 { delta - len } else { delta }
+    };
+
+    (
+        wrap(start.x(), end.x(), size.x()),
+        wrap(start.y(), end.y(), size.y()),
+    )
+}
+
+/// Number of tiles walked between two positions on a map that wraps around
+pub fn toroidal_distance(start: UPosition, end: UPosition, size: Size) -> u64 {
+    let (dx, dy) = toroidal_delta(start, end, size);
+    dx.unsigned_abs() + dy.unsigned_abs()
+}
+
 mod tests {
     #[allow(unused_imports)]
     use super::*;
@@ -95,4 +148,148 @@ mod tests {
         let pos_clone = pos;
         assert_eq!(pos, pos_clone);
     }
+
+    #[test]
+    fn test_toroidal_delta() {
+        let map_size = Size::new(10, 8);
+
+        let start = UPosition::new(0, 6);
+        let end = UPosition::new(9, 3);
+
+        let (dx, dy) = toroidal_delta(start, end, map_size);
+
+        assert_eq!(dx, -1);
+        assert_eq!(dy, -3);
+    }
+
+    #[test]
+    fn test_toroidal_delta_wraparound() {
+        let map_size = Size::new(10, 8);
+
+        let start = UPosition::new(9, 3);
+        let end = UPosition::new(0, 6);
+
+        let (dx, dy) = toroidal_delta(start, end, map_size);
+
+        assert_eq!(dx, 1);
+        assert_eq!(dy, 3);
+    }
+
+    #[test]
+    fn test_toroidal_delta_wraparound_negative() {
+        let map_size = Size::new(10, 8);
+
+        let start = UPosition::new(0, 3);
+        let end = UPosition::new(9, 6);
+
+        let (dx, dy) = toroidal_delta(start, end, map_size);
+
+        assert_eq!(dx, -1);
+        assert_eq!(dy, 3);
+    }
+
+    #[test]
+    fn test_toroidal_delta_wraparound_negative_y() {
+        let map_size = Size::new(10, 8);
+
+        let start = UPosition::new(5, 0);
+        let end = UPosition::new(5, 7);
+
+        let (dx, dy) = toroidal_delta(start, end, map_size);
+
+        assert_eq!(dx, 0);
+        assert_eq!(dy, -1);
+    }
+
+    #[test]
+    fn test_toroidal_delta_wraparound_negative_x() {
+        let map_size = Size::new(10, 8);
+
+        let start = UPosition::new(0, 3);
+        let end = UPosition::new(9, 3);
+
+        let (dx, dy) = toroidal_delta(start, end, map_size);
+
+        assert_eq!(dx, -1);
+        assert_eq!(dy, 0);
+    }
+
+    #[test]
+    fn test_toroidal_delta_wraparound_negative_x_y() {
+        let map_size = Size::new(10, 8);
+
+        let start = UPosition::new(0, 0);
+        let end = UPosition::new(9, 7);
+
+        let (dx, dy) = toroidal_delta(start, end, map_size);
+
+        assert_eq!(dx, -1);
+        assert_eq!(dy, -1);
+    }
+
+    #[test]
+    fn test_toroidal_delta_wraparound_negative_x_y_2() {
+        let map_size = Size::new(10, 8);
+
+        let start = UPosition::new(9, 7);
+        let end = UPosition::new(0, 0);
+
+        let (dx, dy) = toroidal_delta(start, end, map_size);
+
+        assert_eq!(dx, 1);
+        assert_eq!(dy, 1);
+    }
+
+    #[test]
+    fn test_toroidal_distance() {
+        let map_size = Size::new(10, 8);
+
+        assert_eq!(
+            toroidal_distance(UPosition::new(2, 2), UPosition::new(2, 2), map_size),
+            0
+        );
+        assert_eq!(
+            toroidal_distance(UPosition::new(1, 1), UPosition::new(4, 3), map_size),
+            5
+        );
+        assert_eq!(
+            toroidal_distance(UPosition::new(0, 0), UPosition::new(9, 7), map_size),
+            2
+        );
+        assert_eq!(
+            toroidal_distance(UPosition::new(0, 0), UPosition::new(5, 4), map_size),
+            9
+        );
+    }
+
+    #[test]
+    fn test_vec2_add_sub() {
+        let a = Position::new(3, -2);
+        let b = Position::new(-1, 5);
+        assert_eq!(a + b, Position::new(2, 3));
+        assert_eq!(a - b, Position::new(4, -7));
+        assert_eq!(a + b - b, a);
+    }
+
+    #[test]
+    fn test_wrapping_add_at_boundaries() {
+        let size = Size::new(10, 8);
+
+        assert_eq!(
+            UPosition::new(9, 7).wrapping_add(Position::new(1, 1), size),
+            UPosition::new(0, 0)
+        );
+        assert_eq!(
+            UPosition::new(0, 0).wrapping_add(Position::new(-1, -1), size),
+            UPosition::new(9, 7)
+        );
+        assert_eq!(
+            UPosition::new(5, 4).wrapping_add(Position::new(0, 0), size),
+            UPosition::new(5, 4)
+        );
+        assert_eq!(
+            UPosition::new(0, 0).wrapping_add(Position::new(-21, 17), size),
+            UPosition::new(9, 1)
+        );
+    }
 }